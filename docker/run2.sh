@@ -6,4 +6,22 @@ export RUSTUP_HOME=/rustup-home
 export SOURCE_DIR=/source
 export CARGO_TARGET_DIR=/target
 
-$@
+# Count instructions with perf when asked to and it's installed. Ubuntu's
+# /usr/bin/perf wrapper insists on tools matching the running kernel, so use
+# the binary directly. Docker's default seccomp profile blocks
+# perf_event_open, in which case perf refuses to run the command at all, so
+# check that it can count before wrapping the command; without a perf file
+# cargobomb records no instruction count.
+PERF=$(ls /usr/lib/linux-tools/*/perf 2>/dev/null | head -n1)
+if [ -n "$PERF_FILE" ] && [ -n "$PERF" ]; then
+    if "$PERF" stat -e instructions:u -o /dev/null -- true 2>/dev/null; then
+        set -- "$PERF" stat -x, -e instructions:u -o "$PERF_FILE" -- $@
+    fi
+fi
+
+# Record peak memory and CPU time of the command for cargobomb to collect
+if [ -n "$USAGE_FILE" ]; then
+    /usr/bin/time -o "$USAGE_FILE" -f "%M %U %S %e" $@
+else
+    $@
+fi
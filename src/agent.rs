@@ -0,0 +1,192 @@
+// Agents test the crates handed out by a cargobomb server, so that an
+// experiment can be spread over many machines. Each claim is a lease on
+// one crate and toolchain, renewed while testing and ended by uploading
+// the results, or by reporting why the crate couldn't be tested.
+
+use errors::*;
+use ex::{self, Experiment};
+use ex_run;
+use reqwest;
+use reqwest::header::{Authorization, Bearer};
+use results::{CrateResultWriter, ExperimentResultDB, FileDB};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use server::api::{agent_claim, agent_fail, agent_renew};
+use std::collections::HashSet;
+use std::env;
+use std::io::Read;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use toolchain::Toolchain;
+use util;
+
+// How long to wait before asking for work again when there is none
+const IDLE_SECS: u64 = 60;
+
+/// Processes work from the server at `server` until killed, with the
+/// token in `CARGOBOMB_TOKEN`, which needs the agent scope.
+pub fn run(server: &str, agent: &str) -> Result<()> {
+    if !util::is_safe_name(agent) {
+        bail!("invalid agent name {}, which may only have ASCII letters, digits, \
+               '.', '_' and '-'",
+              agent);
+    }
+    let server = server.trim_right_matches('/');
+    let token = env::var("CARGOBOMB_TOKEN").ok();
+    if token.is_none() {
+        warn!("CARGOBOMB_TOKEN isn't set, the server will likely refuse work");
+    }
+    let client = reqwest::Client::new()?;
+    // The experiments already prepared for on this machine
+    let mut prepared = HashSet::new();
+
+    info!("agent {} taking work from {}", agent, server);
+    loop {
+        let claim: Result<agent_claim::Response> =
+            post_json(&client,
+                      &format!("{}/api/agent/claim", server),
+                      &token,
+                      &agent_claim::Request { agent: agent.to_string() });
+        match claim {
+            Ok(agent_claim::Response { work: Some(work) }) => {
+                if let Err(e) = process(&client, server, &token, agent, &work, &mut prepared) {
+                    error!("error testing crate {} against {} for {}",
+                           work.krate,
+                           work.toolchain.to_string(),
+                           work.experiment);
+                    util::report_error(&e);
+                    // Otherwise the lease would only end by expiring
+                    let fail: Result<()> =
+                        post_json(&client,
+                                  &format!("{}/api/agent/fail/{}", server, work.lease),
+                                  &token,
+                                  &agent_fail::Request {
+                                      agent: agent.to_string(),
+                                      error: e.iter()
+                                          .map(|e| e.to_string())
+                                          .collect::<Vec<_>>()
+                                          .join(": "),
+                                  });
+                    if let Err(e) = fail {
+                        util::report_error(&e);
+                    }
+                }
+            }
+            Ok(agent_claim::Response { work: None }) => {
+                info!("no work available, waiting {} s", IDLE_SECS);
+                thread::sleep(Duration::from_secs(IDLE_SECS));
+            }
+            Err(e) => {
+                util::report_error(&e);
+                thread::sleep(Duration::from_secs(IDLE_SECS));
+            }
+        }
+    }
+}
+
+fn process(client: &reqwest::Client,
+           server: &str,
+           token: &Option<String>,
+           agent: &str,
+           work: &agent_claim::Work,
+           prepared: &mut HashSet<String>)
+           -> Result<()> {
+    if !prepared.contains(&work.experiment) {
+        prepare(client, server, token, &work.experiment)?;
+        prepared.insert(work.experiment.clone());
+    }
+    let ex = Experiment::load(&work.experiment)?;
+
+    // Renew the lease until testing is over, when `stop` is dropped
+    let (stop, stopped) = mpsc::channel::<()>();
+    let renew_url = format!("{}/api/agent/renew/{}", server, work.lease);
+    let request = agent_renew::Request { agent: agent.to_string() };
+    let interval = Duration::from_secs(work.lease_secs as u64 / 3);
+    let renew_token = token.clone();
+    let renewer = thread::spawn(move || {
+        let client = match reqwest::Client::new() {
+            Ok(client) => client,
+            Err(e) => return util::report_error(&Error::from(e)),
+        };
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let res: Result<agent_renew::Response> =
+                post_json(&client, &renew_url, &renew_token, &request);
+            match res {
+                Ok(ref res) if !res.renewed => error!("lease expired while testing"),
+                Ok(_) => (),
+                Err(ref e) => util::report_error(e),
+            }
+        }
+    });
+
+    let r = ex_run::run_crate(&ex, &work.toolchain, &work.krate);
+    drop(stop);
+    let _ = renewer.join();
+    let r = r?;
+    info!("test result! ex: {}, c: {}, tc: {}, r: {}",
+          ex.name,
+          work.krate,
+          work.toolchain.to_string(),
+          r);
+
+    let archive = FileDB::for_experiment(&ex)
+        .for_crate(&work.krate, &work.toolchain)
+        .read_archive()?;
+    let url = format!("{}/api/agent/complete/{}/{}", server, work.lease, agent);
+    let res = authorize(client.post(url.as_str()), token)
+        .body(archive)
+        .send()?;
+    if !res.status().is_success() {
+        bail!("unable to upload results: {}", res.status());
+    }
+
+    Ok(())
+}
+
+/// Fetches the experiment's shared data from the server, and does the
+/// local part of `prepare-ex`.
+fn prepare(client: &reqwest::Client,
+           server: &str,
+           token: &Option<String>,
+           ex_name: &str)
+           -> Result<()> {
+    info!("preparing for experiment {}", ex_name);
+    let url = format!("{}/api/ex/{}/shared", server, ex_name);
+    let mut res = authorize(client.get(url.as_str()), token).send()?;
+    if !res.status().is_success() {
+        bail!("unable to fetch experiment {}: {}", ex_name, res.status());
+    }
+    let mut data = Vec::new();
+    res.read_to_end(&mut data)?;
+    ex::unpack_shared_data(ex_name, &data)?;
+
+    let ex = Experiment::load(ex_name)?;
+    ex.fetch_repo_crates()?;
+    ex::download_crates(&ex)?;
+    ex::delete_all_target_dirs(&ex.name)?;
+    ex::fetch_deps(&ex, &Toolchain::Dist("stable".into()))?;
+    ex::prepare_all_toolchains(&ex)
+}
+
+fn post_json<T, R>(client: &reqwest::Client,
+                   url: &str,
+                   token: &Option<String>,
+                   body: &T)
+                   -> Result<R>
+    where T: Serialize,
+          R: DeserializeOwned
+{
+    let mut res = authorize(client.post(url), token).json(body).send()?;
+    if !res.status().is_success() {
+        bail!("request to {} failed: {}", url, res.status());
+    }
+    Ok(res.json()?)
+}
+
+fn authorize(req: reqwest::RequestBuilder, token: &Option<String>) -> reqwest::RequestBuilder {
+    match *token {
+        Some(ref token) => req.header(Authorization(Bearer { token: token.clone() })),
+        None => req,
+    }
+}
@@ -0,0 +1,150 @@
+// Grouping of regressions by the signature of their failure, so that one
+// root cause shows up once in the report instead of once per crate
+
+use diagnostics::Diagnostic;
+use ice::Ice;
+use std::collections::BTreeMap;
+
+/// A set of regressed crates that failed in the same way.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cluster {
+    pub signature: String,
+    pub kind: SignatureKind,
+    pub crates: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SignatureKind {
+    /// An internal compiler error, fingerprinted by its message and panic
+    /// location
+    Ice,
+    /// A dependency, rather than the crate itself, failed to compile
+    Dependency,
+    /// A compiler error in the crate, by error code and normalised message
+    Error,
+    TestFailure,
+    Unknown,
+}
+
+/// Works out the failure signature of a regressed crate from the compiler
+/// errors, internal compiler error and log of its failing run.
+/// `dependency` is the dependency that failed to build, if any, as found by
+/// `diagnostics::failed_dependency`.
+pub fn signature(diagnostics: &[Diagnostic],
+                 ice: Option<&Ice>,
+                 log: &str,
+                 dependency: Option<&str>)
+                 -> (SignatureKind, String) {
+    if let Some(ice) = ice {
+        return (SignatureKind::Ice, ice_fingerprint(ice));
+    }
+
+    if let Some(dep) = dependency {
+        return (SignatureKind::Dependency, dep.to_string());
+    }
+
+    if let Some(d) = diagnostics.first() {
+        let message = normalize(&d.message);
+        let sig = match d.code {
+            Some(ref code) => format!("{}: {}", code, message),
+            None => message,
+        };
+        return (SignatureKind::Error, sig);
+    }
+
+    if log.contains("test result: FAILED") {
+        return (SignatureKind::TestFailure, "test failure".to_string());
+    }
+
+    (SignatureKind::Unknown, "unknown".to_string())
+}
+
+/// Groups crates by signature, largest groups first.
+pub fn cluster(signatures: Vec<(String, SignatureKind, String)>) -> Vec<Cluster> {
+    let mut map: BTreeMap<(SignatureKind, String), Vec<String>> = BTreeMap::new();
+    for (name, kind, sig) in signatures {
+        map.entry((kind, sig)).or_insert_with(Vec::new).push(name);
+    }
+
+    let mut clusters = map.into_iter()
+        .map(|((kind, signature), crates)| {
+                 Cluster {
+                     signature: signature,
+                     kind: kind,
+                     crates: crates,
+                 }
+             })
+        .collect::<Vec<_>>();
+    clusters.sort_by(|a, b| b.crates.len().cmp(&a.crates.len()));
+    clusters
+}
+
+/// "ICE: <message> at <location>", normalised so that the same ICE in
+/// different crates compares equal.
+fn ice_fingerprint(ice: &Ice) -> String {
+    let message = normalize(&ice.message);
+    match ice.location {
+        Some(ref location) => format!("ICE: {} at {}", message, normalize(location)),
+        None => format!("ICE: {}", message),
+    }
+}
+
+/// Strips the crate-specific parts of a message so that the same error in
+/// different crates compares equal: quoted names become `_` and numbers
+/// become N.
+pub fn normalize(message: &str) -> String {
+    let mut out = String::new();
+    let mut in_quote = false;
+    let mut in_number = false;
+    for c in message.chars() {
+        if c == '`' {
+            if !in_quote {
+                out.push_str("`_`");
+            }
+            in_quote = !in_quote;
+            continue;
+        }
+        if in_quote {
+            continue;
+        }
+        if c.is_digit(10) {
+            if !in_number {
+                out.push('N');
+            }
+            in_number = true;
+            continue;
+        }
+        in_number = false;
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_ices_by_message_and_location() {
+        let ice = Ice {
+            message: "unexpected panic".to_string(),
+            location: Some("/checkout/src/librustc/ty/mod.rs:2063".to_string()),
+            backtrace: Vec::new(),
+        };
+        // The log isn't consulted, even when it has other panics
+        let log = "thread 'main' panicked at 'no libfoo found', build.rs:4";
+        assert_eq!(signature(&[], Some(&ice), log, Some("dep 0.1.0")),
+                   (SignatureKind::Ice,
+                    "ICE: unexpected panic at /checkout/src/librustc/ty/mod.rs:N".to_string()));
+        assert_eq!(signature(&[], None, log, None),
+                   (SignatureKind::Unknown, "unknown".to_string()));
+    }
+
+    #[test]
+    fn normalizes_messages() {
+        assert_eq!(normalize("cannot find type `Foo` in module `bar`"),
+                   "cannot find type `_` in module `_`");
+        assert_eq!(normalize("expected 2 parameters, found 13"),
+                   "expected N parameters, found N");
+    }
+}
@@ -0,0 +1,234 @@
+// Compiler diagnostics, collected from the output of cargo's
+// `--message-format=json`
+
+use serde_json;
+
+/// An error reported by rustc while building a crate or one of its
+/// dependencies. Warnings are not kept.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Diagnostic {
+    /// The cargo package id of the crate being compiled, e.g.
+    /// "foo 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)"
+    pub package_id: String,
+    pub message: String,
+    /// The error code, e.g. "E0277", if the error has one
+    pub code: Option<String>,
+    /// The file and line of the primary span
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    /// The error as rustc would have printed it
+    pub rendered: Option<String>,
+}
+
+impl Diagnostic {
+    /// A short description of the error, like "E0277 in src/lib.rs:42".
+    pub fn summary(&self) -> String {
+        let what = match self.code {
+            Some(ref code) => code.clone(),
+            None => format!("error '{}'", self.message),
+        };
+        match (&self.file, self.line) {
+            (&Some(ref file), Some(line)) => format!("{} in {}:{}", what, file, line),
+            (&Some(ref file), None) => format!("{} in {}", what, file),
+            _ => what,
+        }
+    }
+}
+
+/// Works out which dependency, if any, failed to compile and caused the
+/// crate's build to fail, returning its name and version, like "foo 0.1.0".
+/// The first error-level compiler message is where the build broke, so its
+/// package is consulted first, then cargo's "could not compile" line in the
+/// log, which only names the package; that fallback needs the name of the
+/// crate under test.
+pub fn failed_dependency(diagnostics: &[Diagnostic],
+                         log: &str,
+                         crate_name: Option<&str>)
+                         -> Option<String> {
+    // Only error-level messages are kept, in the order cargo printed them
+    if let Some(d) = diagnostics.first() {
+        let name = package_name(&d.package_id);
+        let is_crate = is_root_package(&d.package_id) ||
+                       crate_name.map_or(false, |c| name.split(' ').next() == Some(c));
+        return if is_crate { None } else { Some(name) };
+    }
+
+    let crate_name = match crate_name {
+        Some(name) => name,
+        None => return None,
+    };
+    // Older cargos capitalize the message
+    const COULD_NOT_COMPILE: &'static [&'static str] = &["could not compile `",
+                                                         "Could not compile `"];
+    log.lines()
+        .filter_map(|line| {
+            COULD_NOT_COMPILE
+                .iter()
+                .filter_map(|pat| line.find(pat).map(|i| &line[i + pat.len()..]))
+                .next()
+                .and_then(|rest| rest.find('`').map(|j| rest[..j].to_string()))
+        })
+        .find(|name| name != crate_name)
+}
+
+/// The crate under test is mounted at /source in the container, so its
+/// package ids, and those of its workspace members, are path packages
+/// there.
+pub fn is_root_package(package_id: &str) -> bool {
+    package_id.contains("(path+file:///source")
+}
+
+/// "foo 0.1.0 (registry+...)" -> "foo 0.1.0"
+pub fn package_name(package_id: &str) -> String {
+    match package_id.find(" (") {
+        Some(i) => package_id[..i].to_string(),
+        None => package_id.to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    package_id: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    message: String,
+    level: String,
+    code: Option<ErrorCode>,
+    #[serde(default)]
+    spans: Vec<Span>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    file_name: String,
+    line_start: u64,
+    is_primary: bool,
+}
+
+/// The lines to log for a line of cargo's `--message-format=json` output:
+/// compiler messages as rustc would have printed them, and nothing for the
+/// other messages, such as the artifacts built. `None` if the line isn't a
+/// cargo message.
+pub fn rendered_lines(line: &str) -> Option<Vec<String>> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let msg = match serde_json::from_str::<CargoMessage>(line) {
+        Ok(msg) => msg,
+        Err(_) => return None,
+    };
+    let rendered = msg.message.and_then(|m| m.rendered).unwrap_or_default();
+    Some(rendered
+             .trim_right()
+             .lines()
+             .map(|l| l.to_string())
+             .collect())
+}
+
+/// Extracts the compiler errors from the stdout of a cargo command run with
+/// `--message-format=json`. Lines that aren't cargo messages are ignored.
+pub fn parse_cargo_messages(lines: &[String]) -> Vec<Diagnostic> {
+    lines
+        .iter()
+        .filter(|line| line.starts_with('{'))
+        .filter_map(|line| serde_json::from_str::<CargoMessage>(line).ok())
+        .filter(|msg| msg.reason == "compiler-message")
+        .filter_map(|msg| {
+            let package_id = msg.package_id;
+            msg.message.and_then(|m| {
+                if !m.level.starts_with("error") {
+                    return None;
+                }
+                let (file, line) = match m.spans.iter().find(|s| s.is_primary) {
+                    Some(span) => (Some(span.file_name.clone()), Some(span.line_start)),
+                    None => (None, None),
+                };
+                Some(Diagnostic {
+                         package_id: package_id,
+                         message: m.message,
+                         code: m.code.map(|c| c.code),
+                         file: file,
+                         line: line,
+                         rendered: m.rendered,
+                     })
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The stdout of `cargo build --message-format=json` with a dependency
+    // failing to build, trimmed of the fields that aren't read
+    const BUILD_OUTPUT: &'static str = r#"{"reason":"compiler-artifact","package_id":"libc 0.2.23 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"crate_types":["lib"],"name":"libc","src_path":"/cargo-home/registry/src/github.com-1ecc6299db9ec823/libc-0.2.23/src/lib.rs"},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":["default","use_std"],"filenames":["/target/debug/deps/liblibc-8c4d7d4f45d0a5d9.rlib"],"fresh":false}
+{"reason":"compiler-message","package_id":"dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"crate_types":["lib"],"name":"dep","src_path":"/cargo-home/registry/src/github.com-1ecc6299db9ec823/dep-0.1.0/src/lib.rs"},"message":{"children":[],"code":{"code":"E0308","explanation":null},"level":"error","message":"mismatched types","rendered":"error[E0308]: mismatched types\n --> src/lib.rs:1:21\n  |\n1 | pub fn f() -> u32 { \"x\" }\n  |                     ^^^ expected u32, found reference\n\n","spans":[{"byte_end":23,"byte_start":20,"column_end":24,"column_start":21,"expansion":null,"file_name":"src/lib.rs","is_primary":true,"label":"expected u32, found reference","line_end":1,"line_start":1,"suggested_replacement":null,"text":[]}]}}
+{"reason":"compiler-message","package_id":"dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)","target":{"kind":["lib"],"crate_types":["lib"],"name":"dep","src_path":"/cargo-home/registry/src/github.com-1ecc6299db9ec823/dep-0.1.0/src/lib.rs"},"message":{"children":[],"code":null,"level":"error","message":"aborting due to previous error","rendered":"error: aborting due to previous error\n\n","spans":[]}}
+{"reason":"compiler-message","package_id":"foo 0.1.0 (path+file:///source)","target":{"kind":["lib"],"crate_types":["lib"],"name":"foo","src_path":"/source/src/lib.rs"},"message":{"children":[],"code":null,"level":"warning","message":"unused variable: `x`","rendered":"warning: unused variable: `x`\n --> src/lib.rs:2:18\n  |\n2 | pub fn g() { let x = 1; }\n  |                  ^\n\n","spans":[{"byte_end":36,"byte_start":35,"column_end":19,"column_start":18,"expansion":null,"file_name":"src/lib.rs","is_primary":true,"label":null,"line_end":2,"line_start":2,"suggested_replacement":null,"text":[]}]}}"#;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_errors() {
+        let mut stdout = lines(BUILD_OUTPUT);
+        stdout.push("error: build failed".to_string());
+        let diagnostics = parse_cargo_messages(&stdout);
+        assert_eq!(diagnostics.len(), 2);
+
+        let d = &diagnostics[0];
+        assert_eq!(d.package_id,
+                   "dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)");
+        assert_eq!(d.message, "mismatched types");
+        assert_eq!(d.code, Some("E0308".to_string()));
+        assert_eq!(d.summary(), "E0308 in src/lib.rs:1");
+        assert!(d.rendered
+                    .as_ref()
+                    .unwrap()
+                    .starts_with("error[E0308]: mismatched types\n"));
+
+        assert_eq!(diagnostics[1].summary(), "error 'aborting due to previous error'");
+    }
+
+    #[test]
+    fn renders_messages_for_the_log() {
+        let stdout = lines(BUILD_OUTPUT);
+        assert_eq!(rendered_lines(&stdout[0]), Some(Vec::new()));
+        let rendered = rendered_lines(&stdout[1]).unwrap();
+        assert_eq!(rendered[0], "error[E0308]: mismatched types");
+        assert_eq!(rendered[1], " --> src/lib.rs:1:21");
+        assert_eq!(rendered.len(), 5);
+        assert_eq!(rendered_lines("   Compiling foo v0.1.0 (file:///source)"), None);
+        assert_eq!(rendered_lines("{not json"), None);
+    }
+
+    #[test]
+    fn finds_the_failed_dependency() {
+        let diagnostics = parse_cargo_messages(&lines(BUILD_OUTPUT));
+        assert_eq!(failed_dependency(&diagnostics, "", Some("foo")),
+                   Some("dep 0.1.0".to_string()));
+        // The crate under test failing isn't a dependency failing
+        assert_eq!(failed_dependency(&diagnostics, "", Some("dep")), None);
+    }
+
+    #[test]
+    fn finds_the_failed_dependency_in_the_log() {
+        let log = "error: Could not compile `dep`.\nerror: Could not compile `foo`.";
+        assert_eq!(failed_dependency(&[], log, Some("foo")), Some("dep".to_string()));
+        assert_eq!(failed_dependency(&[], log, Some("dep")), Some("foo".to_string()));
+        assert_eq!(failed_dependency(&[], log, None), None);
+    }
+}
@@ -33,5 +33,8 @@ pub const FROB_DIR: &'static str = "./work/shared/fromls";
 pub const EXPERIMENT_DIR: &'static str = "./work/ex";
 pub const LOG_DIR: &'static str = "./work/logs";
 
+// The server's experiment queue, and the reports it generates
+pub const SERVER_DIR: &'static str = "./work/server";
+
 // State for asynchronous job management
 pub const JOB_DIR: &'static str = "./work/jobs";
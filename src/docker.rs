@@ -1,6 +1,7 @@
 use dirs::{CARGO_HOME, RUSTUP_HOME};
 use errors::*;
-use run;
+use file;
+use run::{self, ProcessOutput};
 use std::env;
 use std::fmt::{self, Display, Formatter};
 use std::fs;
@@ -8,6 +9,13 @@ use std::path::{Path, PathBuf};
 
 static IMAGE_NAME: &'static str = "cargobomb";
 
+// The file, relative to the target directory, that run2.sh asks
+// `/usr/bin/time` to write the resource usage of the command into
+static USAGE_FILE: &'static str = "cargobomb-usage.txt";
+// The file, relative to the target directory, that run2.sh asks `perf stat`
+// to write the instruction count of the command into
+static PERF_FILE: &'static str = "cargobomb-perf.txt";
+
 /// Builds the docker container image, 'cargobomb', what will be used
 /// to isolate builds from each other. This expects the Dockerfile
 /// to exist in the `docker` directory, at runtime.
@@ -27,6 +35,8 @@ pub struct RustEnv<'a> {
     pub cargo_home: (PathBuf, Perm),
     pub rustup_home: (PathBuf, Perm),
     pub target_dir: (PathBuf, Perm),
+    /// Whether to count the instructions the command retires with `perf`
+    pub count_instructions: bool,
 }
 
 pub struct MountConfig<'a> {
@@ -48,6 +58,54 @@ impl<'a> MountConfig<'a> {
     }
 }
 
+/// A Linux cross target whose binaries are linked with a cross gcc and
+/// executed under qemu-user inside the container. These are configured
+/// through cargo's `target.$triple.{linker,runner}` settings.
+struct QemuTarget {
+    target: &'static str,
+    linker_var: &'static str,
+    linker: &'static str,
+    runner_var: &'static str,
+    runner: &'static str,
+}
+
+static QEMU_TARGETS: &'static [QemuTarget] = &[
+    QemuTarget {
+        target: "aarch64-unknown-linux-gnu",
+        linker_var: "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER",
+        linker: "aarch64-linux-gnu-gcc",
+        runner_var: "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_RUNNER",
+        runner: "qemu-aarch64 -L /usr/aarch64-linux-gnu",
+    },
+    QemuTarget {
+        target: "armv7-unknown-linux-gnueabihf",
+        linker_var: "CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_LINKER",
+        linker: "arm-linux-gnueabihf-gcc",
+        runner_var: "CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_RUNNER",
+        runner: "qemu-arm -L /usr/arm-linux-gnueabihf",
+    },
+    QemuTarget {
+        target: "arm-unknown-linux-gnueabihf",
+        linker_var: "CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_LINKER",
+        linker: "arm-linux-gnueabihf-gcc",
+        runner_var: "CARGO_TARGET_ARM_UNKNOWN_LINUX_GNUEABIHF_RUNNER",
+        runner: "qemu-arm -L /usr/arm-linux-gnueabihf",
+    },
+];
+
+// Cross targets the x86_64 container can execute without emulation
+static NATIVE_TARGETS: &'static [&'static str] = &[
+    "x86_64-unknown-linux-musl",
+    "i686-unknown-linux-gnu",
+    "i686-unknown-linux-musl",
+];
+
+/// Whether test binaries built for `target` can be run inside the
+/// container, either natively or under qemu-user.
+pub fn can_run_target(target: &str) -> bool {
+    NATIVE_TARGETS.contains(&target) || QEMU_TARGETS.iter().any(|t| t.target == target)
+}
+
 pub struct ContainerConfig<'a> {
     pub image_name: &'a str,
     pub mounts: Vec<MountConfig<'a>>,
@@ -55,10 +113,61 @@ pub struct ContainerConfig<'a> {
 }
 
 
-pub fn run(source_path: &Path, target_path: &Path, args: &[&str]) -> Result<()> {
+/// Resources consumed by a command run in a container, as reported by
+/// GNU time.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ResourceUsage {
+    /// Peak resident set size, in kilobytes
+    pub max_rss_kb: u64,
+    pub user_secs: f64,
+    pub sys_secs: f64,
+    pub wall_secs: f64,
+    /// Instructions retired in user space. Only counted for compile-time
+    /// experiments, and only when the docker daemon's seccomp profile
+    /// permits `perf_event_open`.
+    #[serde(default)]
+    pub instructions: Option<u64>,
+}
+
+impl ResourceUsage {
+    pub fn cpu_secs(&self) -> f64 {
+        self.user_secs + self.sys_secs
+    }
+}
+
+/// The outcome of running a command in a container.
+pub struct RunOutput {
+    pub result: Result<()>,
+    pub usage: Option<ResourceUsage>,
+    /// Lines the command wrote to stdout
+    pub stdout: Vec<String>,
+    /// Lines the command wrote to stderr
+    pub stderr: Vec<String>,
+}
+
+pub fn run(source_path: &Path,
+           target_path: &Path,
+           args: &[&str],
+           count_instructions: bool)
+           -> RunOutput {
 
     info!("running: {}", args.join(" "));
 
+    let usage_file = target_path.join(USAGE_FILE);
+    let perf_file = target_path.join(PERF_FILE);
+    for file in &[&usage_file, &perf_file] {
+        if file.exists() {
+            if let Err(e) = fs::remove_file(file) {
+                return RunOutput {
+                           result: Err(e.into()),
+                           usage: None,
+                           stdout: Vec::new(),
+                           stderr: Vec::new(),
+                       };
+            }
+        }
+    }
+
     let env = RustEnv {
         args: args,
         work_dir: (source_path.into(), Perm::ReadOnly),
@@ -66,12 +175,96 @@ pub fn run(source_path: &Path, target_path: &Path, args: &[&str]) -> Result<()>
         rustup_home: (Path::new(RUSTUP_HOME).into(), Perm::ReadOnly),
         // This is configured as CARGO_TARGET_DIR by the docker container itself
         target_dir: (target_path.into(), Perm::ReadWrite),
+        count_instructions: count_instructions,
     };
 
-    run_container(rust_container(env))
+    let (result, stdout, stderr): (Result<()>, Vec<String>, Vec<String>) =
+        match run_container(rust_container(env)) {
+            Ok(out) => {
+                if out.status.success() {
+                    (Ok(()), out.stdout, out.stderr)
+                } else {
+                    (Err(format!("container command `{}` failed", args.join(" ")).into()),
+                     out.stdout,
+                     out.stderr)
+                }
+            }
+            Err(e) => (Err(e), Vec::new(), Vec::new()),
+        };
+    let mut usage = match read_usage(&usage_file) {
+        Ok(usage) => usage,
+        Err(e) => {
+            error!("unable to read resource usage: {}", e);
+            None
+        }
+    };
+    if let Some(ref mut usage) = usage {
+        usage.instructions = match read_instructions(&perf_file) {
+            Ok(instructions) => instructions,
+            Err(e) => {
+                error!("unable to read instruction count: {}", e);
+                None
+            }
+        };
+    }
+
+    RunOutput {
+        result: result,
+        usage: usage,
+        stdout: stdout,
+        stderr: stderr,
+    }
 }
 
-pub fn run_container(config: ContainerConfig) -> Result<()> {
+fn read_usage(path: &Path) -> Result<Option<ResourceUsage>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    // time writes a "Command exited with non-zero status" line before the
+    // formatted one when the command fails
+    let lines = file::read_lines(path)?;
+    let line = match lines.last() {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 4 {
+        bail!("malformed resource usage: {}", line);
+    }
+    let parse = |s: &str| -> Result<f64> {
+        s.parse::<f64>()
+            .chain_err(|| format!("malformed resource usage: {}", line))
+    };
+
+    Ok(Some(ResourceUsage {
+                max_rss_kb: parse(fields[0])? as u64,
+                user_secs: parse(fields[1])?,
+                sys_secs: parse(fields[2])?,
+                wall_secs: parse(fields[3])?,
+                instructions: None,
+            }))
+}
+
+fn read_instructions(path: &Path) -> Result<Option<u64>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    // `perf stat -x,` writes CSV lines of the form
+    // "123456,,instructions:u,..." with "<not supported>" or
+    // "<not counted>" in place of the count when it is unavailable
+    for line in file::read_lines(path)? {
+        let fields = line.split(',').collect::<Vec<_>>();
+        if fields.len() > 2 && fields[2].starts_with("instructions") {
+            return Ok(fields[0].parse::<u64>().ok());
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn run_container(config: ContainerConfig) -> Result<ProcessOutput> {
     let c = Container::create_container(config)?;
     defer!{{
         if let Err(e) = c.delete() {
@@ -107,11 +300,23 @@ pub fn rust_container(config: RustEnv) -> ContainerConfig {
         },
     ];
 
-    let env = vec![
+    let mut env = vec![
         ("USER_ID", format!("{}", user_id())),
         ("CMD", config.args.join(" ")),
+        ("USAGE_FILE", format!("/target/{}", USAGE_FILE)),
+        // So that internal compiler errors come with a backtrace
+        ("RUST_BACKTRACE", "1".to_string()),
     ];
 
+    if config.count_instructions {
+        env.push(("PERF_FILE", format!("/target/{}", PERF_FILE)));
+    }
+
+    for t in QEMU_TARGETS {
+        env.push((t.linker_var, t.linker.to_string()));
+        env.push((t.runner_var, t.runner.to_string()));
+    }
+
     ContainerConfig {
         image_name: IMAGE_NAME,
         mounts: mounts,
@@ -170,8 +375,8 @@ impl Container {
         Ok(Self { id: out[0].clone() })
     }
 
-    pub fn run(&self) -> Result<()> {
-        run::run("docker", &["start", "-a", &self.id], &[])
+    pub fn run(&self) -> Result<ProcessOutput> {
+        run::run_output("docker", &["start", "-a", &self.id], &[])
     }
 
     pub fn delete(&self) -> Result<()> {
@@ -15,5 +15,21 @@ error_chain! {
             description("the operation timed out")
             display("process killed after {} {}s", what, when)
         }
+        NotFound(what: String) {
+            description("not found")
+            display("{} not found", what)
+        }
+        BadRequest(why: String) {
+            description("bad request")
+            display("{}", why)
+        }
+        Unauthorized(why: String) {
+            description("unauthorized")
+            display("{}", why)
+        }
+        Forbidden(why: String) {
+            description("forbidden")
+            display("{}", why)
+        }
     }
 }
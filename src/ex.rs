@@ -2,15 +2,21 @@ use crates;
 use dirs::{CRATES_DIR, EXPERIMENT_DIR, TEST_SOURCE_DIR};
 use errors::*;
 use file;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use gh_mirrors;
 use lists::{self, Crate, List};
+use registry;
+use results::{ExperimentResultDB, FileDB};
 use run;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use tar::{Archive, Builder};
 use toml_frobber;
 use toolchain::{self, Toolchain};
 use util;
@@ -22,6 +28,8 @@ pub enum ExMode {
     BuildOnly,
     CheckOnly,
     UnstableFeatures,
+    CompileTime,
+    Bench,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -30,6 +38,8 @@ pub enum ExCrateSelect {
     Demo,
     SmallRandom,
     Top100,
+    /// A crate and the crates whose latest versions depend on it
+    RDeps(String),
 }
 
 
@@ -57,7 +67,7 @@ fn froml_dir(ex_name: &str) -> PathBuf {
     Path::new(EXPERIMENT_DIR).join(ex_name).join("fromls")
 }
 
-fn froml_path(ex_name: &str, name: &str, vers: &str) -> PathBuf {
+pub fn froml_path(ex_name: &str, name: &str, vers: &str) -> PathBuf {
     froml_dir(ex_name).join(format!("{}-{}.Cargo.toml", name, vers))
 }
 
@@ -67,6 +77,10 @@ pub struct Experiment {
     pub crates: Vec<Crate>,
     pub toolchains: Vec<Toolchain>,
     pub mode: ExMode,
+    /// Cross-compilation targets each crate is additionally built for.
+    /// Empty for experiments that only build for the host.
+    #[serde(default)]
+    pub targets: Vec<String>,
 }
 
 pub struct ExOpts {
@@ -74,17 +88,29 @@ pub struct ExOpts {
     pub toolchains: Vec<Toolchain>,
     pub mode: ExMode,
     pub crates: ExCrateSelect,
+    pub targets: Vec<String>,
 }
 
 pub fn define(opts: ExOpts) -> Result<()> {
+    if let ExMode::Bench = opts.mode {
+        // #[bench] is unstable
+        for tc in &opts.toolchains {
+            if let Toolchain::Dist(ref name) = *tc {
+                if !name.starts_with("nightly") {
+                    bail!("bench experiments require nightly toolchains, not {}", name);
+                }
+            }
+        }
+    }
     delete(&opts.name)?;
     let crates = match opts.crates {
         ExCrateSelect::Full => lists::read_all_lists()?,
         ExCrateSelect::Demo => demo_list()?,
         ExCrateSelect::SmallRandom => small_random()?,
         ExCrateSelect::Top100 => top_100()?,
+        ExCrateSelect::RDeps(ref krate) => rdeps(krate)?,
     };
-    define_(&opts.name, opts.toolchains, crates, opts.mode)
+    define_(&opts.name, opts.toolchains, crates, opts.mode, opts.targets)
 }
 
 fn demo_list() -> Result<Vec<Crate>> {
@@ -131,7 +157,39 @@ fn top_100() -> Result<Vec<Crate>> {
     Ok(crates)
 }
 
-pub fn define_(ex_name: &str, tcs: Vec<Toolchain>, crates: Vec<Crate>, mode: ExMode) -> Result<()> {
+fn rdeps(krate: &str) -> Result<Vec<Crate>> {
+    let index = registry::crates_index_registry()?;
+    let rdeps = index
+        .crates()
+        .filter(|c| {
+                    c.latest_version()
+                        .dependencies()
+                        .iter()
+                        .any(|d| d.name() == krate)
+                })
+        .map(|c| c.name().to_string())
+        .collect::<HashSet<_>>();
+
+    let crates = lists::read_all_lists()?
+        .into_iter()
+        .filter(|c| match *c {
+                    Crate::Version { ref name, .. } => name == krate || rdeps.contains(name),
+                    Crate::Repo { .. } => false,
+                })
+        .collect::<Vec<_>>();
+    if crates.is_empty() {
+        bail!("no crate {} in the crate lists", krate);
+    }
+
+    Ok(crates)
+}
+
+pub fn define_(ex_name: &str,
+               tcs: Vec<Toolchain>,
+               crates: Vec<Crate>,
+               mode: ExMode,
+               targets: Vec<String>)
+               -> Result<()> {
     info!("defining experiment {} for {} crates",
           ex_name,
           crates.len());
@@ -140,6 +198,7 @@ pub fn define_(ex_name: &str, tcs: Vec<Toolchain>, crates: Vec<Crate>, mode: ExM
         crates: crates,
         toolchains: tcs,
         mode: mode,
+        targets: targets,
     };
     fs::create_dir_all(&ex_dir(&ex.name))?;
     let json = serde_json::to_string(&ex)?;
@@ -150,7 +209,11 @@ pub fn define_(ex_name: &str, tcs: Vec<Toolchain>, crates: Vec<Crate>, mode: ExM
 
 impl Experiment {
     pub fn load(ex_name: &str) -> Result<Self> {
-        let config = file::read_string(&config_file(ex_name))?;
+        let path = config_file(ex_name);
+        if !path.exists() {
+            bail!(ErrorKind::NotFound(format!("experiment {}", ex_name)));
+        }
+        let config = file::read_string(&path)?;
         Ok(serde_json::from_str(&config)?)
     }
 
@@ -347,7 +410,7 @@ fn lockfile_dir(ex_name: &str) -> PathBuf {
     Path::new(EXPERIMENT_DIR).join(ex_name).join("lockfiles")
 }
 
-fn lockfile(ex_name: &str, crate_: &ExCrate) -> Result<PathBuf> {
+pub fn lockfile(ex_name: &str, crate_: &ExCrate) -> Result<PathBuf> {
     let (crate_name, crate_vers) = match *crate_ {
         ExCrate::Version {
             ref name,
@@ -489,9 +552,29 @@ pub fn fetch_deps(ex: &Experiment, toolchain: &Toolchain) -> Result<()> {
 
 }
 
+/// Prepares the shared data for an experiment, and then the local data for
+/// running it on this machine, deleting any previous results.
+pub fn prepare(ex: &Experiment) -> Result<()> {
+    let stable = Toolchain::Dist("stable".into());
+
+    // Shared experiment prep
+    ex.fetch_repo_crates()?;
+    capture_shas(ex)?;
+    download_crates(ex)?;
+    frob_tomls(ex)?;
+    capture_lockfiles(ex, &stable, false)?;
+
+    // Local experiment prep
+    delete_all_target_dirs(&ex.name)?;
+    FileDB::for_experiment(ex).delete_all_results()?;
+    fetch_deps(ex, &stable)?;
+    prepare_all_toolchains(ex)
+}
+
 pub fn prepare_all_toolchains(ex: &Experiment) -> Result<()> {
     for tc in &ex.toolchains {
         tc.prepare()?;
+        tc.install_targets(&ex.targets)?;
     }
 
     Ok(())
@@ -512,6 +595,33 @@ pub fn copy(ex1_name: &str, ex2_name: &str) -> Result<()> {
     util::copy_dir(ex1_dir, ex2_dir)
 }
 
+/// Packs the data an experiment is run from, its config, shas, frobbed
+/// tomls and lockfiles, into a gzipped tarball, for running it on other
+/// machines.
+pub fn pack_shared_data(ex_name: &str) -> Result<Vec<u8>> {
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::Default));
+    builder.append_file("config.json", &mut fs::File::open(config_file(ex_name))?)?;
+    let shafile = shafile(ex_name);
+    if shafile.exists() {
+        builder.append_file("shas.json", &mut fs::File::open(shafile)?)?;
+    }
+    for (name, dir) in vec![("fromls", froml_dir(ex_name)), ("lockfiles", lockfile_dir(ex_name))] {
+        if dir.exists() {
+            builder.append_dir_all(name, dir)?;
+        }
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+/// Unpacks data packed by `pack_shared_data` into the experiment's
+/// directory, defining it if needed.
+pub fn unpack_shared_data(ex_name: &str, data: &[u8]) -> Result<()> {
+    let dir = ex_dir(ex_name);
+    fs::create_dir_all(&dir)?;
+    Archive::new(GzDecoder::new(data)?).unpack(&dir)?;
+    Ok(())
+}
+
 pub fn delete_all_target_dirs(ex_name: &str) -> Result<()> {
     let target_dir = &toolchain::ex_target_dir(ex_name);
     if target_dir.exists() {
@@ -530,6 +640,23 @@ pub fn delete(ex_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// The names of the defined experiments, in order.
+pub fn list() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if !Path::new(EXPERIMENT_DIR).exists() {
+        return Ok(names);
+    }
+    for entry in fs::read_dir(EXPERIMENT_DIR)? {
+        if let Some(name) = entry?.file_name().to_str() {
+            if config_file(name).exists() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
 impl FromStr for ExMode {
     type Err = Error;
 
@@ -539,6 +666,8 @@ impl FromStr for ExMode {
                "build-only" => ExMode::BuildOnly,
                "check-only" => ExMode::CheckOnly,
                "unstable-features" => ExMode::UnstableFeatures,
+               "compile-time" => ExMode::CompileTime,
+               "bench" => ExMode::Bench,
                s => bail!("invalid ex-mode: {}", s),
            })
     }
@@ -551,6 +680,8 @@ impl ExMode {
             ExMode::BuildOnly => "build-only",
             ExMode::CheckOnly => "check-only",
             ExMode::UnstableFeatures => "unstable-features",
+            ExMode::CompileTime => "compile-time",
+            ExMode::Bench => "bench",
         }
     }
 }
@@ -564,18 +695,22 @@ impl FromStr for ExCrateSelect {
                "demo" => ExCrateSelect::Demo,
                "small-random" => ExCrateSelect::SmallRandom,
                "top-100" => ExCrateSelect::Top100,
+               s if s.starts_with("rdeps:") && s.len() > "rdeps:".len() => {
+                   ExCrateSelect::RDeps(s["rdeps:".len()..].to_string())
+               }
                s => bail!("invalid crate-select: {}", s),
            })
     }
 }
 
-impl ExCrateSelect {
-    pub fn to_str(&self) -> &'static str {
+impl Display for ExCrateSelect {
+    fn fmt(&self, f: &mut Formatter) -> ::std::result::Result<(), fmt::Error> {
         match *self {
-            ExCrateSelect::Full => "full",
-            ExCrateSelect::Demo => "demo",
-            ExCrateSelect::SmallRandom => "small-random",
-            ExCrateSelect::Top100 => "top-100",
+            ExCrateSelect::Full => write!(f, "full"),
+            ExCrateSelect::Demo => write!(f, "demo"),
+            ExCrateSelect::SmallRandom => write!(f, "small-random"),
+            ExCrateSelect::Top100 => write!(f, "top-100"),
+            ExCrateSelect::RDeps(ref krate) => write!(f, "rdeps:{}", krate),
         }
     }
 }
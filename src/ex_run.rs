@@ -1,14 +1,24 @@
+use chrono::UTC;
+use diagnostics;
+use docker::{self, RunOutput};
 use errors::*;
 use ex::*;
 use file;
+use ice;
 use ref_slice::ref_slice;
-use results::{CrateResultWriter, ExperimentResultDB, FileDB, TestResult};
-use std::collections::HashSet;
+use libtest;
+use progress::{CurrentTest, Progress};
+use results::{CrateResultWriter, ExperimentResultDB, FileDB, TestOutput, TestResult};
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
 use std::time::Instant;
 use toolchain::Toolchain;
 use util;
 
+// The number of clean builds per crate and toolchain in compile-time
+// experiments
+const COMPILE_TIME_ITERATIONS: usize = 3;
+
 pub fn delete_all_results(ex_name: &str) -> Result<()> {
     let ex = &Experiment::load(ex_name)?;
     let db = FileDB::for_experiment(ex);
@@ -54,15 +64,10 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
     let mut sum_build_fail = 0;
     let mut sum_test_fail = 0;
     let mut sum_test_pass = 0;
+    let mut sum_ice = 0;
 
     let start_time = Instant::now();
-
-    let test_fn = match ex.mode {
-        ExMode::BuildAndTest => test_build_and_test,
-        ExMode::BuildOnly => test_build_only,
-        ExMode::CheckOnly => test_check_only,
-        ExMode::UnstableFeatures => test_find_unstable_features,
-    };
+    let mut progress = Progress::new(tcs, crates.len());
 
     info!("running {} tests", total_crates);
     for (ref c, _) in crates {
@@ -83,18 +88,14 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
                 } else {
                     completed_crates += 1;
 
-                    with_work_crate(ex, tc, c, |source_path| {
-                        with_frobbed_toml(ex, c, source_path)?;
-                        with_captured_lockfile(ex, c, source_path)?;
-
-                        writer.record_results(|| {
-                                                  info!("testing {} against {} for {}",
-                                                        c,
-                                                        tc.to_string(),
-                                                        ex.name);
-                                                  test_fn(ex, source_path, tc)
-                                              })
-                    })
+                    progress.current = vec![CurrentTest {
+                                                krate: c.to_string(),
+                                                toolchain: tc.to_string(),
+                                                agent: None,
+                                                started: UTC::now().to_rfc3339(),
+                                            }];
+                    progress.save(&ex.name)?;
+                    test_crate(ex, tc, c, &writer)
                 }
             };
 
@@ -120,6 +121,7 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
                 Ok(TestResult::BuildFail) => sum_build_fail += 1,
                 Ok(TestResult::TestFail) => sum_test_fail += 1,
                 Ok(TestResult::TestPass) => sum_test_pass += 1,
+                Ok(TestResult::Ice) => sum_ice += 1,
             }
 
             let elapsed = Instant::now().duration_since(start_time).as_secs();
@@ -139,6 +141,15 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
                 format!("{:0} hours", remaining_time / 60 / 60)
             };
 
+            progress.record(tc, r.as_ref().ok());
+            progress.current.clear();
+            progress.eta_secs = if completed_crates > 0 {
+                Some(remaining_time as u64)
+            } else {
+                None
+            };
+            progress.save(&ex.name)?;
+
             info!("progress: {} / {}",
                   completed_crates + skipped_crates,
                   total_crates);
@@ -148,10 +159,11 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
                   seconds_per_test,
                   remaining_tests,
                   remaining_time_str);
-            info!("results: {} build-fail / {} test-fail / {} test-pass / {} errors",
+            info!("results: {} build-fail / {} test-fail / {} test-pass / {} ice / {} errors",
                   sum_build_fail,
                   sum_test_fail,
                   sum_test_pass,
+                  sum_ice,
                   sum_errors);
         }
     }
@@ -159,6 +171,49 @@ fn run_exts(ex: &Experiment, tcs: &[Toolchain]) -> Result<()> {
     Ok(())
 }
 
+/// Tests a single crate against a toolchain, replacing any existing
+/// result. This is how agents run the work handed out by the server.
+pub fn run_crate(ex: &Experiment, tc: &Toolchain, c: &ExCrate) -> Result<TestResult> {
+    verify_toolchains(ex, ref_slice(tc))?;
+    let writer = FileDB::for_experiment(ex).for_crate(c, tc);
+    writer.delete_result()?;
+    test_crate(ex, tc, c, &writer)
+}
+
+fn test_crate<W: CrateResultWriter>(ex: &Experiment,
+                                    tc: &Toolchain,
+                                    c: &ExCrate,
+                                    writer: &W)
+                                    -> Result<TestResult> {
+    let test_fn = match ex.mode {
+        ExMode::BuildAndTest => test_build_and_test,
+        ExMode::BuildOnly => test_build_only,
+        ExMode::CheckOnly => test_check_only,
+        ExMode::UnstableFeatures => test_find_unstable_features,
+        ExMode::CompileTime => test_compile_time,
+        ExMode::Bench => test_bench,
+    };
+
+    with_work_crate(ex, tc, c, |source_path| {
+        with_frobbed_toml(ex, c, source_path)?;
+        with_captured_lockfile(ex, c, source_path)?;
+
+        let mut output = TestOutput::default();
+        let r = writer.record_results(|| {
+            info!("testing {} against {} for {}", c, tc.to_string(), ex.name);
+            let r = test_fn(ex, source_path, tc, &mut output)?;
+            if !ex.targets.is_empty() {
+                output.targets = test_targets(ex, source_path, tc);
+            }
+            Ok(r)
+        });
+        if r.is_ok() {
+            writer.record_output(&output)?;
+        }
+        r
+    })
+}
+
 fn verify_toolchains(config: &Experiment, tcs: &[Toolchain]) -> Result<()> {
     for tc in tcs {
         if !config.toolchains.contains(tc) {
@@ -169,28 +224,67 @@ fn verify_toolchains(config: &Experiment, tcs: &[Toolchain]) -> Result<()> {
     Ok(())
 }
 
+/// Records the internal compiler error hit by a cargo command, if any,
+/// returning whether testing has hit one.
+fn record_ice(out: &RunOutput, output: &mut TestOutput) -> bool {
+    if output.ice.is_none() {
+        output.ice = ice::find_ice(&out.stderr);
+    }
+    output.ice.is_some()
+}
+
 fn test_build_and_test(ex: &Experiment,
                        source_path: &Path,
-                       toolchain: &Toolchain)
+                       toolchain: &Toolchain,
+                       output: &mut TestOutput)
                        -> Result<TestResult> {
-    let build_r = toolchain.run_cargo_in_docker(&ex.name, source_path, &["build", "--frozen"]);
+    let build_out = toolchain.run_cargo_in_docker_measured(&ex.name,
+                                                           source_path,
+                                                           &["build",
+                                                             "--frozen",
+                                                             "--message-format=json"]);
+    output.usage.build = build_out.usage;
+    output.diagnostics = diagnostics::parse_cargo_messages(&build_out.stdout);
+    if record_ice(&build_out, output) {
+        return Ok(TestResult::Ice);
+    }
+    let build_r = build_out.result;
     let mut test_r;
 
     if build_r.is_ok() {
         // First build, with --no-run
-        test_r = Some(toolchain.run_cargo_in_docker(&ex.name,
-                                                    source_path.into(),
-                                                    &["test", "--frozen", "--no-run"]));
+        let test_build_out = toolchain.run_cargo_in_docker_measured(&ex.name,
+                                                                    source_path.into(),
+                                                                    &["test",
+                                                                      "--frozen",
+                                                                      "--no-run",
+                                                                      "--message-format=json"]);
+        output
+            .diagnostics
+            .extend(diagnostics::parse_cargo_messages(&test_build_out.stdout));
+        if record_ice(&test_build_out, output) {
+            return Ok(TestResult::Ice);
+        }
+        test_r = Some(test_build_out.result);
         // Then run
         test_r = test_r.map(|_| {
-                                toolchain.run_cargo_in_docker(&ex.name,
-                                                              source_path.into(),
-                                                              &["test", "--frozen"])
-                            });
+            let test_out = toolchain.run_cargo_in_docker_measured(&ex.name,
+                                                                  source_path.into(),
+                                                                  &["test", "--frozen"]);
+            output.usage.test = test_out.usage;
+            output.tests = libtest::parse_test_output(&test_out.stdout, &test_out.stderr);
+            // rustdoc compiles the doctests
+            record_ice(&test_out, output);
+            test_out.result
+        });
     } else {
         test_r = None;
     }
 
+    if output.ice.is_some() {
+        return Ok(TestResult::Ice);
+    }
+
     Ok(match (build_r, test_r) {
            (Err(_), None) => TestResult::BuildFail,
            (Ok(_), Some(Err(_))) => TestResult::TestFail,
@@ -201,9 +295,17 @@ fn test_build_and_test(ex: &Experiment,
 
 fn test_build_only(ex: &Experiment,
                    source_path: &Path,
-                   toolchain: &Toolchain)
+                   toolchain: &Toolchain,
+                   output: &mut TestOutput)
                    -> Result<TestResult> {
-    let r = toolchain.run_cargo_in_docker(&ex.name, source_path.into(), &["build", "--frozen"]);
+    let args = &["build", "--frozen", "--message-format=json"];
+    let out = toolchain.run_cargo_in_docker_measured(&ex.name, source_path.into(), args);
+    output.usage.build = out.usage;
+    output.diagnostics = diagnostics::parse_cargo_messages(&out.stdout);
+    if record_ice(&out, output) {
+        return Ok(TestResult::Ice);
+    }
+    let r = out.result;
 
     if r.is_ok() {
         Ok(TestResult::TestPass)
@@ -214,9 +316,17 @@ fn test_build_only(ex: &Experiment,
 
 fn test_check_only(ex: &Experiment,
                    source_path: &Path,
-                   toolchain: &Toolchain)
+                   toolchain: &Toolchain,
+                   output: &mut TestOutput)
                    -> Result<TestResult> {
-    let r = toolchain.run_cargo_in_docker(&ex.name, source_path.into(), &["check", "--frozen"]);
+    let args = &["check", "--frozen", "--message-format=json"];
+    let out = toolchain.run_cargo_in_docker_measured(&ex.name, source_path.into(), args);
+    output.usage.build = out.usage;
+    output.diagnostics = diagnostics::parse_cargo_messages(&out.stdout);
+    if record_ice(&out, output) {
+        return Ok(TestResult::Ice);
+    }
+    let r = out.result;
 
     if r.is_ok() {
         Ok(TestResult::TestPass)
@@ -225,9 +335,138 @@ fn test_check_only(ex: &Experiment,
     }
 }
 
+/// Run the crate's benchmarks, recording the libtest timings.
+fn test_bench(ex: &Experiment,
+              source_path: &Path,
+              toolchain: &Toolchain,
+              output: &mut TestOutput)
+              -> Result<TestResult> {
+    let build_out = toolchain.run_cargo_in_docker_measured(&ex.name,
+                                                           source_path,
+                                                           &["build",
+                                                             "--frozen",
+                                                             "--message-format=json"]);
+    output.usage.build = build_out.usage;
+    output.diagnostics = diagnostics::parse_cargo_messages(&build_out.stdout);
+    if record_ice(&build_out, output) {
+        return Ok(TestResult::Ice);
+    }
+    if build_out.result.is_err() {
+        return Ok(TestResult::BuildFail);
+    }
+
+    let bench_out = toolchain.run_cargo_in_docker_measured(&ex.name,
+                                                           source_path,
+                                                           &["bench", "--frozen"]);
+    output.usage.test = bench_out.usage;
+    output.benches = libtest::parse_bench_output(&bench_out.stdout);
+    if record_ice(&bench_out, output) {
+        return Ok(TestResult::Ice);
+    }
+
+    if bench_out.result.is_ok() {
+        Ok(TestResult::TestPass)
+    } else {
+        Ok(TestResult::TestFail)
+    }
+}
+
+/// Build the crate from clean `COMPILE_TIME_ITERATIONS` times, recording
+/// the time taken by each build, and the instructions of each build that
+/// reported them. The toolchain's target directory is deleted before
+/// every build, so dependencies are rebuilt too.
+fn test_compile_time(ex: &Experiment,
+                     source_path: &Path,
+                     toolchain: &Toolchain,
+                     output: &mut TestOutput)
+                     -> Result<TestResult> {
+    let target_dir = toolchain.target_dir(&ex.name);
+
+    for i in 0..COMPILE_TIME_ITERATIONS {
+        if target_dir.exists() {
+            util::remove_dir_all(&target_dir)?;
+        }
+
+        info!("compile-time build {} of {}", i + 1, COMPILE_TIME_ITERATIONS);
+        let out = toolchain.run_cargo_in_docker_counted(&ex.name,
+                                                        source_path,
+                                                        &["build", "--frozen"]);
+        if record_ice(&out, output) {
+            return Ok(TestResult::Ice);
+        }
+        if out.result.is_err() {
+            return Ok(TestResult::BuildFail);
+        }
+        // A build without a sample is still a build; the comparison only
+        // uses the samples that were recorded, and none if there are none
+        match out.usage {
+            Some(u) => output.usage.compile_samples.push(u),
+            None => error!("no resource usage recorded for compile-time build {}", i + 1),
+        }
+    }
+
+    Ok(TestResult::TestPass)
+}
+
+/// Build the crate for each of the experiment's cross-compilation targets,
+/// producing one row of the crate-platform compatibility matrix. In
+/// build-and-test mode the tests are also run for targets the container
+/// can execute, under qemu-user for non-x86 architectures.
+fn test_targets(ex: &Experiment,
+                source_path: &Path,
+                toolchain: &Toolchain)
+                -> BTreeMap<String, TestResult> {
+    let cmd = match ex.mode {
+        ExMode::BuildAndTest | ExMode::BuildOnly => "build",
+        ExMode::CheckOnly => "check",
+        ExMode::UnstableFeatures |
+        ExMode::CompileTime |
+        ExMode::Bench => return BTreeMap::new(),
+    };
+
+    ex.targets
+        .iter()
+        .map(|target| {
+            info!("building for target {}", target);
+            let build_r = toolchain.run_cargo_in_docker(&ex.name,
+                                                        source_path,
+                                                        &[cmd, "--frozen", "--target", target]);
+            let run_tests = match ex.mode {
+                ExMode::BuildAndTest => docker::can_run_target(target),
+                _ => false,
+            };
+
+            let res = if build_r.is_err() {
+                TestResult::BuildFail
+            } else if !run_tests {
+                TestResult::TestPass
+            } else {
+                info!("testing for target {}", target);
+                let test_r = toolchain
+                    .run_cargo_in_docker(&ex.name,
+                                         source_path,
+                                         &["test", "--frozen", "--no-run", "--target", target])
+                    .and_then(|_| {
+                                  toolchain.run_cargo_in_docker(&ex.name,
+                                                                source_path,
+                                                                &["test", "--frozen", "--target",
+                                                                  target])
+                              });
+                if test_r.is_ok() {
+                    TestResult::TestPass
+                } else {
+                    TestResult::TestFail
+                }
+            };
+            (target.to_string(), res)
+        })
+        .collect()
+}
+
 fn test_find_unstable_features(_ex: &Experiment,
                                source_path: &Path,
-                               _toolchain: &Toolchain)
+                               _toolchain: &Toolchain,
+                               _output: &mut TestOutput)
                                -> Result<TestResult> {
     use walkdir::*;
 
@@ -0,0 +1,264 @@
+// Synchronization of an experiment's shared data with S3-compatible
+// object storage, so that it can be prepared and run from several machines
+//
+// Each experiment has a manifest at `<prefix>/ex/<name>/manifest.json`
+// mapping its files to blobs, which hold their contents once each, named
+// by their SHA-256, under `<prefix>/ex/<name>/blobs/`. Blobs belong to one
+// experiment so that they can be deleted once its manifest no longer
+// refers to them. The manifest as of the last push or pull is kept in the
+// experiment's `sync.json`, and is what local and remote changes are
+// compared against to detect conflicts and deletions.
+
+use errors::*;
+use ex;
+use file;
+use s3::{self, S3Client, S3Opts, key};
+use serde_json;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// The files and directories of an experiment that are shared between
+// machines. Everything else is local state.
+const SHARED_FILES: &'static [&'static str] = &["config.json", "shas.json"];
+const SHARED_DIRS: &'static [&'static str] = &["fromls", "lockfiles", "res"];
+
+/// Paths relative to the experiment directory, mapped to the SHA-256 of
+/// their contents.
+type Manifest = BTreeMap<String, String>;
+
+fn sync_file(ex_name: &str) -> PathBuf {
+    ex::ex_dir(ex_name).join("sync.json")
+}
+
+fn manifest_key(opts: &S3Opts, ex_name: &str) -> String {
+    key(&opts.prefix, &format!("ex/{}/manifest.json", ex_name))
+}
+
+fn blob_key(opts: &S3Opts, ex_name: &str, hash: &str) -> String {
+    key(&opts.prefix, &format!("ex/{}/blobs/{}", ex_name, hash))
+}
+
+/// Uploads the experiment's shared files that changed since the last
+/// sync, and deletes those deleted since. Files changed remotely in the
+/// meantime are left alone, unless they were changed locally too, which is
+/// a conflict.
+pub fn push(ex_name: &str, opts: &S3Opts) -> Result<()> {
+    let dir = ex::ex_dir(ex_name);
+    if !dir.exists() {
+        bail!("experiment {} is not defined", ex_name);
+    }
+
+    let client = opts.connect()?;
+    let mut base = load_base(ex_name)?;
+    let local = local_manifest(ex_name)?;
+    let remote = remote_manifest(&client, opts, ex_name)?.unwrap_or_default();
+    check_conflicts(ex_name, &base, &local, &remote)?;
+
+    let known = remote.values().cloned().collect::<HashSet<_>>();
+    let mut manifest = remote.clone();
+    let mut uploaded = 0;
+    for (path, hash) in &local {
+        if base.get(path) == Some(hash) || remote.get(path) == Some(hash) {
+            continue;
+        }
+
+        let blob = blob_key(opts, ex_name, hash);
+        if !known.contains(hash) && client.etag(&blob)?.is_none() {
+            let body = read_file(&dir.join(path))?;
+            if s3::sha256_hex(&body) != *hash {
+                bail!("{} changed while pushing", path);
+            }
+            info!("uploading {}", path);
+            client.put(&blob, body, "application/octet-stream", None)?;
+            uploaded += 1;
+        }
+        manifest.insert(path.clone(), hash.clone());
+    }
+    // Files deleted locally since the last sync, unless they were changed
+    // remotely meanwhile
+    let mut deleted = 0;
+    for (path, hash) in &base {
+        if !local.contains_key(path) && remote.get(path) == Some(hash) {
+            manifest.remove(path);
+            deleted += 1;
+        }
+    }
+
+    // S3 can't update the manifest atomically, so this only narrows the
+    // window in which concurrent pushes lose each other's changes
+    if remote_manifest(&client, opts, ex_name)?.unwrap_or_default() != remote {
+        bail!("experiment {} was pushed from elsewhere meanwhile; pull and push again",
+              ex_name);
+    }
+    info!("updating manifest of {}", ex_name);
+    client.put(&manifest_key(opts, ex_name),
+               serde_json::to_vec(&manifest)?,
+               "application/json",
+               None)?;
+    info!("uploaded {} files, deleted {}", uploaded, deleted);
+
+    // Only after the manifest stops referring to them
+    let kept = manifest.values().collect::<HashSet<_>>();
+    for hash in remote.values().collect::<HashSet<_>>() {
+        if !kept.contains(hash) {
+            client.delete(&blob_key(opts, ex_name, hash))?;
+        }
+    }
+
+    for (path, hash) in &local {
+        if manifest.get(path) == Some(hash) {
+            base.insert(path.clone(), hash.clone());
+        }
+    }
+    let gone = base.keys()
+        .filter(|path| !local.contains_key(*path) && !manifest.contains_key(*path))
+        .cloned()
+        .collect::<Vec<_>>();
+    for path in gone {
+        base.remove(&path);
+    }
+    save_base(ex_name, &base)
+}
+
+/// Downloads the experiment's shared files that changed remotely since
+/// the last sync, and deletes those deleted remotely, defining the
+/// experiment locally if needed. Files changed only locally are kept.
+pub fn pull(ex_name: &str, opts: &S3Opts) -> Result<()> {
+    let client = opts.connect()?;
+    let remote = match remote_manifest(&client, opts, ex_name)? {
+        Some(remote) => remote,
+        None => bail!("experiment {} has not been pushed", ex_name),
+    };
+
+    let dir = ex::ex_dir(ex_name);
+    fs::create_dir_all(&dir)?;
+    let mut base = load_base(ex_name)?;
+    let local = local_manifest(ex_name)?;
+    check_conflicts(ex_name, &base, &local, &remote)?;
+
+    let mut downloaded = 0;
+    for (path, hash) in &remote {
+        if path.split('/').any(|c| c.is_empty() || c == "." || c == "..") {
+            bail!("invalid path {} in manifest of {}", path, ex_name);
+        }
+        let changed_locally = local.contains_key(path) && base.get(path) == Some(hash);
+        if local.get(path) != Some(hash) && !changed_locally {
+            let body = client
+                .get(&blob_key(opts, ex_name, hash))?
+                .ok_or_else(|| format!("blob {} of {} is missing", hash, path))?;
+            if s3::sha256_hex(&body) != *hash {
+                bail!("blob {} of {} is corrupt", hash, path);
+            }
+
+            info!("downloading {}", path);
+            let dest = dir.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(&dest)?.write_all(&body)?;
+            downloaded += 1;
+        }
+        if !changed_locally {
+            base.insert(path.clone(), hash.clone());
+        }
+    }
+    let gone = base.keys()
+        .filter(|path| !remote.contains_key(*path))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut deleted = 0;
+    for path in gone {
+        if local.get(&path) == base.get(&path) {
+            info!("deleting {}", path);
+            fs::remove_file(dir.join(&path))?;
+            deleted += 1;
+        }
+        base.remove(&path);
+    }
+    info!("downloaded {} files, deleted {}", downloaded, deleted);
+
+    save_base(ex_name, &base)
+}
+
+/// Fails if any file was changed both locally and remotely since the last
+/// sync, to different contents. Results written for the same crate on two
+/// machines end up here.
+fn check_conflicts(ex_name: &str,
+                   base: &Manifest,
+                   local: &Manifest,
+                   remote: &Manifest)
+                   -> Result<()> {
+    let conflicts = local
+        .iter()
+        .filter(|&(path, hash)| match remote.get(path) {
+                    Some(remote_hash) => {
+                        let base_hash = base.get(path);
+                        remote_hash != hash && base_hash != Some(hash) &&
+                        base_hash != Some(remote_hash)
+                    }
+                    None => false,
+                })
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>();
+
+    if !conflicts.is_empty() {
+        bail!("conflicting changes to {} files of experiment {}, delete the local copies \
+               and pull again to keep the remote ones:\n{}",
+              conflicts.len(),
+              ex_name,
+              conflicts.join("\n"));
+    }
+    Ok(())
+}
+
+fn local_manifest(ex_name: &str) -> Result<Manifest> {
+    let dir = ex::ex_dir(ex_name);
+    let mut manifest = Manifest::new();
+    for name in SHARED_FILES.iter().chain(SHARED_DIRS) {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        for entry in WalkDir::new(&path) {
+            let entry = entry.chain_err(|| "walk dir")?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel = entry.path().strip_prefix(&dir).expect("");
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            manifest.insert(rel, s3::sha256_hex(&read_file(entry.path())?));
+        }
+    }
+    Ok(manifest)
+}
+
+fn remote_manifest(client: &S3Client,
+                   opts: &S3Opts,
+                   ex_name: &str)
+                   -> Result<Option<Manifest>> {
+    match client.get(&manifest_key(opts, ex_name))? {
+        Some(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        None => Ok(None),
+    }
+}
+
+fn load_base(ex_name: &str) -> Result<Manifest> {
+    let path = sync_file(ex_name);
+    if !path.exists() {
+        return Ok(Manifest::new());
+    }
+    Ok(serde_json::from_str(&file::read_string(&path)?)?)
+}
+
+fn save_base(ex_name: &str, base: &Manifest) -> Result<()> {
+    file::write_string(&sync_file(ex_name), &serde_json::to_string(base)?)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    File::open(path)?.read_to_end(&mut body)?;
+    Ok(body)
+}
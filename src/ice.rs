@@ -0,0 +1,138 @@
+// Detection of internal compiler errors in the output of the compiler
+
+/// An internal compiler error, extracted from the stderr of a cargo
+/// command run with `RUST_BACKTRACE=1`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ice {
+    /// The ICE message, or the panic message if rustc didn't report one
+    pub message: String,
+    /// Where in the compiler the panic happened, e.g.
+    /// "src/librustc/ty/mod.rs:2063"
+    pub location: Option<String>,
+    /// The frames of the backtrace, empty if none was printed
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub backtrace: Vec<String>,
+}
+
+const ICE: &'static str = "error: internal compiler error:";
+// Panics in other threads come from build scripts or tests, not the
+// compiler
+const RUSTC_PANIC: &'static str = "thread 'rustc' panicked at '";
+
+/// Looks for an internal compiler error in `lines`, which rustc reports
+/// as
+///
+/// ```text
+/// error: internal compiler error: unexpected panic
+/// thread 'rustc' panicked at 'index out of bounds', src/librustc/ty/mod.rs:2063
+/// stack backtrace:
+///    0: ...
+/// ```
+///
+/// With `--message-format=json` the first line goes to stdout as JSON,
+/// but the panic is still printed, so either line is enough.
+pub fn find_ice(lines: &[String]) -> Option<Ice> {
+    let ice_message = lines
+        .iter()
+        .filter_map(|l| l.find(ICE).map(|i| l[i + ICE.len()..].trim().to_string()))
+        .next();
+    let panic = lines
+        .iter()
+        .filter_map(|l| l.find(RUSTC_PANIC).map(|i| &l[i + RUSTC_PANIC.len()..]))
+        .next()
+        .map(parse_panic);
+
+    let (message, location) = match (ice_message, panic) {
+        (Some(message), Some((_, location))) => (message, location),
+        (Some(message), None) => (message, None),
+        (None, Some((message, location))) => (message, location),
+        (None, None) => return None,
+    };
+
+    Some(Ice {
+             message: message,
+             location: location,
+             backtrace: find_backtrace(lines),
+         })
+}
+
+/// "msg', src/foo.rs:1" -> ("msg", Some("src/foo.rs:1"))
+fn parse_panic(rest: &str) -> (String, Option<String>) {
+    match rest.rfind("', ") {
+        Some(i) => (rest[..i].to_string(), Some(rest[i + 3..].trim().to_string())),
+        None => (rest.trim_right_matches('\'').to_string(), None),
+    }
+}
+
+/// The indented lines following "stack backtrace:".
+fn find_backtrace(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .skip_while(|l| !l.contains("stack backtrace:"))
+        .skip(1)
+        .take_while(|l| l.starts_with(' '))
+        .map(|l| l.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    // The stderr of `cargo build` hitting an ICE in rustc 1.19.0-nightly
+    const ICE_STDERR: &'static str = "   Compiling foo v0.1.0 (file:///source)
+error: internal compiler error: unexpected panic
+
+note: the compiler unexpectedly panicked. this is a bug.
+
+note: we would appreciate a bug report: \
+https://github.com/rust-lang/rust/blob/master/CONTRIBUTING.md#bug-reports
+
+note: rustc 1.19.0-nightly (5b13bff52 2017-05-23) running on x86_64-unknown-linux-gnu
+
+thread 'rustc' panicked at 'index out of bounds: the len is 0 but the index is 0', \
+/checkout/src/librustc/ty/mod.rs:2063
+stack backtrace:
+   0: std::sys::imp::backtrace::tracing::imp::unwind_backtrace
+   1: std::panicking::default_hook::{{closure}}
+   2: rustc::ty::TyCtxt::item_name
+error: Could not compile `foo`.
+
+To learn more, run the command again with --verbose.
+";
+
+    #[test]
+    fn finds_ices() {
+        let ice = find_ice(&lines(ICE_STDERR)).unwrap();
+        assert_eq!(ice.message, "unexpected panic");
+        assert_eq!(ice.location,
+                   Some("/checkout/src/librustc/ty/mod.rs:2063".to_string()));
+        assert_eq!(ice.backtrace,
+                   vec!["0: std::sys::imp::backtrace::tracing::imp::unwind_backtrace",
+                        "1: std::panicking::default_hook::{{closure}}",
+                        "2: rustc::ty::TyCtxt::item_name"]);
+    }
+
+    #[test]
+    fn uses_the_panic_without_an_ice_message() {
+        let stderr = lines("thread 'rustc' panicked at 'assertion failed: !ty.needs_infer()', \
+                            /checkout/src/librustc_trans/common.rs:123");
+        let ice = find_ice(&stderr).unwrap();
+        assert_eq!(ice.message, "assertion failed: !ty.needs_infer()");
+        assert_eq!(ice.location,
+                   Some("/checkout/src/librustc_trans/common.rs:123".to_string()));
+        assert!(ice.backtrace.is_empty());
+    }
+
+    #[test]
+    fn ignores_other_panics() {
+        let stderr = lines("error: failed to run custom build command for `foo v0.1.0`
+thread 'main' panicked at 'no libfoo found', build.rs:4
+note: Run with `RUST_BACKTRACE=1` for a backtrace.");
+        assert!(find_ice(&stderr).is_none());
+    }
+}
@@ -24,7 +24,7 @@ extern crate libc;
 extern crate winapi;
 extern crate kernel32;
 extern crate reqwest;
-#[macro_use(slog_o, slog_info, slog_log, slog_error,
+#[macro_use(slog_o, slog_info, slog_warn, slog_log, slog_error,
             slog_record, slog_record_static, slog_b, slog_kv)]
 extern crate slog;
 #[macro_use]
@@ -44,6 +44,11 @@ extern crate hyper;
 extern crate mime;
 extern crate arc_cell;
 extern crate route_recognizer;
+extern crate constant_time_eq;
+extern crate hmac;
+extern crate md5;
+extern crate sha1;
+extern crate sha2;
 
 #[macro_use]
 pub mod log;
@@ -60,10 +65,18 @@ mod crates;
 mod git;
 pub mod ex;
 pub mod ex_run;
+pub mod ex_sync;
 mod toml_frobber;
 mod gh_mirrors;
 pub mod report;
 pub mod docker;
 pub mod dirs;
 mod results;
+mod libtest;
+mod diagnostics;
+mod clusters;
+mod ice;
+mod progress;
+pub mod s3;
 pub mod server;
+pub mod agent;
@@ -0,0 +1,354 @@
+// Parsing of the output of libtest, the harness behind `cargo test` and
+// `cargo bench`
+
+/// The timing of a single `#[bench]` function.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BenchResult {
+    pub name: String,
+    pub ns_per_iter: f64,
+    /// The "+/-" figure libtest reports, the spread between the fastest
+    /// and slowest samples
+    pub deviation: f64,
+}
+
+/// Extracts the benchmark timings from the stdout of `cargo bench`, whose
+/// lines look like
+///
+/// ```text
+/// test bench_parse ... bench:       1,234 ns/iter (+/- 56)
+/// ```
+pub fn parse_bench_output(lines: &[String]) -> Vec<BenchResult> {
+    lines
+        .iter()
+        .filter_map(|line| parse_bench_line(line))
+        .collect()
+}
+
+fn parse_bench_line(line: &str) -> Option<BenchResult> {
+    if !line.starts_with("test ") {
+        return None;
+    }
+    let line = &line["test ".len()..];
+
+    let sep = match line.find(" ... bench:") {
+        Some(i) => i,
+        None => return None,
+    };
+    let name = line[..sep].trim();
+    let rest = &line[sep + " ... bench:".len()..];
+
+    let ns_end = match rest.find("ns/iter") {
+        Some(i) => i,
+        None => return None,
+    };
+    let ns_per_iter = parse_number(&rest[..ns_end]);
+
+    let deviation = rest.find("(+/-")
+        .and_then(|i| {
+                      let dev = &rest[i + "(+/-".len()..];
+                      dev.find(')').and_then(|j| parse_number(&dev[..j]))
+                  })
+        .unwrap_or(0.0);
+
+    ns_per_iter.map(|ns_per_iter| {
+                        BenchResult {
+                            name: name.to_string(),
+                            ns_per_iter: ns_per_iter,
+                            deviation: deviation,
+                        }
+                    })
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    s.trim().replace(',', "").parse::<f64>().ok()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TestStatus {
+    Pass,
+    Fail,
+    Ignored,
+}
+
+/// The outcome of a single test function.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestCase {
+    /// The test binary that ran the test, such as "foo" for
+    /// target/debug/deps/foo-<hash> or "doc-tests foo". Empty when cargo's
+    /// output couldn't be matched up, or for results recorded before
+    /// binaries were.
+    #[serde(default)]
+    pub binary: String,
+    pub name: String,
+    pub status: TestStatus,
+}
+
+impl TestCase {
+    /// The test's name, qualified by its binary when that's known, as
+    /// tests in different binaries can share names.
+    pub fn full_name(&self) -> String {
+        if self.binary.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}: {}", self.binary, self.name)
+        }
+    }
+}
+
+/// Extracts the individual test outcomes from the output of `cargo test`.
+/// Its stdout has lines like
+///
+/// ```text
+/// running 3 tests
+/// test foo::bar ... ok
+/// test baz ... FAILED
+/// test qux ... ignored
+/// ```
+///
+/// for each test binary, so unit, integration and doc tests all appear.
+/// Cargo announces each binary on stderr as it runs it, with lines like
+///
+/// ```text
+///      Running target/debug/deps/foo-0123456789abcdef
+///    Doc-tests foo
+/// ```
+///
+/// and as each binary starts by printing "running N tests", the two pair
+/// up in order.
+pub fn parse_test_output(stdout: &[String], stderr: &[String]) -> Vec<TestCase> {
+    let binaries = stderr
+        .iter()
+        .filter_map(|line| parse_binary_line(line))
+        .collect::<Vec<_>>();
+    let runs = stdout
+        .iter()
+        .filter(|line| line.starts_with("running "))
+        .count();
+    // Without a binary for every run there's no telling which is which
+    let binaries = if binaries.len() == runs {
+        binaries
+    } else {
+        Vec::new()
+    };
+
+    let mut tests = Vec::new();
+    let mut binary = None;
+    let mut run = 0;
+    for line in stdout {
+        if line.starts_with("running ") {
+            binary = binaries.get(run);
+            run += 1;
+        } else if let Some((name, status)) = parse_test_line(line) {
+            tests.push(TestCase {
+                           binary: binary.cloned().unwrap_or_default(),
+                           name: name.to_string(),
+                           status: status,
+                       });
+        }
+    }
+
+    tests
+}
+
+/// The name of the test binary announced by a line of cargo's stderr, if
+/// it's such a line. Newer cargos announce "Running unittests src/lib.rs
+/// (target/debug/deps/foo-<hash>)".
+fn parse_binary_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.starts_with("Doc-tests ") {
+        return Some(format!("doc-tests {}", line["Doc-tests ".len()..].trim()));
+    }
+    if !line.starts_with("Running ") {
+        return None;
+    }
+
+    let path = line.trim_right_matches(')');
+    let path = match path.rfind(|c: char| c == ' ' || c == '(') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    };
+    let file = match path.rfind('/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    };
+    // The hash depends on the toolchain, so it can't be compared
+    let name = match file.rfind('-') {
+        Some(i) if file[i + 1..].chars().all(|c| c.is_digit(16)) => &file[..i],
+        _ => file,
+    };
+    Some(name.to_string())
+}
+
+fn parse_test_line(line: &str) -> Option<(&str, TestStatus)> {
+    if !line.starts_with("test ") {
+        return None;
+    }
+    let line = &line["test ".len()..];
+
+    let sep = match line.rfind(" ... ") {
+        Some(i) => i,
+        None => return None,
+    };
+    let name = line[..sep].trim();
+    let status = match line[sep + " ... ".len()..].trim() {
+        "ok" => TestStatus::Pass,
+        "FAILED" => TestStatus::Fail,
+        "ignored" => TestStatus::Ignored,
+        _ => return None,
+    };
+
+    Some((name, status))
+}
+
+/// The tests that failed in `after` but passed in `before`, by their full
+/// names. Tests are matched by binary and name.
+pub fn newly_failing(before: &[TestCase], after: &[TestCase]) -> Vec<String> {
+    after
+        .iter()
+        .filter(|t| t.status == TestStatus::Fail)
+        .filter(|t| {
+                    before
+                        .iter()
+                        .any(|b| {
+                                 b.binary == t.binary && b.name == t.name &&
+                                 b.status == TestStatus::Pass
+                             })
+                })
+        .map(|t| t.full_name())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|l| l.to_string()).collect()
+    }
+
+    // The stdout of `cargo bench` on a crate with two benchmarks
+    const BENCH_OUTPUT: &'static str = "
+running 3 tests
+test tests::it_works ... ignored
+test benches::bench_add ... bench:           1.26 ns/iter (+/- 0.38)
+test benches::bench_parse ... bench:      12,345 ns/iter (+/- 1,234)
+
+test result: ok. 0 passed; 0 failed; 1 ignored; 2 measured; 0 filtered out
+";
+
+    #[test]
+    fn parses_bench_output() {
+        let benches = parse_bench_output(&lines(BENCH_OUTPUT));
+        assert_eq!(benches.len(), 2);
+        assert_eq!(benches[0].name, "benches::bench_add");
+        assert_eq!(benches[0].ns_per_iter, 1.26);
+        assert_eq!(benches[0].deviation, 0.38);
+        assert_eq!(benches[1].name, "benches::bench_parse");
+        assert_eq!(benches[1].ns_per_iter, 12345.0);
+        assert_eq!(benches[1].deviation, 1234.0);
+    }
+
+    #[test]
+    fn ignores_malformed_bench_lines() {
+        let benches = parse_bench_output(&lines("test foo ... bench: n/a ns/iter (+/- 1)\n\
+                                                 test bar ... bench:   10 ns/iter"));
+        assert_eq!(benches.len(), 1);
+        assert_eq!(benches[0].name, "bar");
+        assert_eq!(benches[0].ns_per_iter, 10.0);
+        assert_eq!(benches[0].deviation, 0.0);
+    }
+
+    // The output of `cargo test` on a crate with failing unit and doc tests
+    // and a passing integration test with the same name as a unit test
+    const TEST_STDOUT: &'static str = "
+running 3 tests
+test tests::it_fails ... FAILED
+test tests::it_works ... ok
+test tests::slow ... ignored
+
+failures:
+
+---- tests::it_fails stdout ----
+thread 'tests::it_fails' panicked at 'assertion failed: `(left == right)`
+  left: `4`,
+ right: `5`', src/lib.rs:16:21
+note: Run with `RUST_BACKTRACE=1` for a backtrace.
+
+
+failures:
+    tests::it_fails
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+
+
+running 1 test
+test tests::it_works ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out
+
+
+running 2 tests
+test src/lib.rs - Foo<T> (line 6) ... FAILED
+test src/lib.rs - add (line 1) ... ok
+
+failures:
+    src/lib.rs - Foo<T> (line 6)
+
+test result: FAILED. 1 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out
+";
+
+    const TEST_STDERR: &'static str = "   Compiling cap v0.1.0 (file:///source)
+    Finished dev [unoptimized + debuginfo] target(s) in 0.68 secs
+     Running /target/debug/deps/cap-628802e2d850edcc
+error: test failed, to rerun pass '--lib'
+     Running tests/smoke.rs (/target/debug/deps/smoke-ed103a3d4a06ceda)
+   Doc-tests cap
+error: test failed, to rerun pass '--doc'
+";
+
+    fn test(binary: &str, name: &str, status: TestStatus) -> TestCase {
+        TestCase {
+            binary: binary.to_string(),
+            name: name.to_string(),
+            status: status,
+        }
+    }
+
+    #[test]
+    fn parses_test_output() {
+        let tests = parse_test_output(&lines(TEST_STDOUT), &lines(TEST_STDERR));
+        let tests = tests
+            .iter()
+            .map(|t| (&t.binary[..], &t.name[..], t.status))
+            .collect::<Vec<_>>();
+        assert_eq!(tests,
+                   vec![("cap", "tests::it_fails", TestStatus::Fail),
+                        ("cap", "tests::it_works", TestStatus::Pass),
+                        ("cap", "tests::slow", TestStatus::Ignored),
+                        ("smoke", "tests::it_works", TestStatus::Pass),
+                        ("doc-tests cap", "src/lib.rs - Foo<T> (line 6)", TestStatus::Fail),
+                        ("doc-tests cap", "src/lib.rs - add (line 1)", TestStatus::Pass)]);
+    }
+
+    #[test]
+    fn leaves_binaries_out_when_runs_dont_match() {
+        let stderr = lines("     Running /target/debug/deps/cap-628802e2d850edcc");
+        let tests = parse_test_output(&lines(TEST_STDOUT), &stderr);
+        assert_eq!(tests.len(), 6);
+        assert!(tests.iter().all(|t| t.binary.is_empty()));
+    }
+
+    #[test]
+    fn finds_newly_failing_tests_by_binary() {
+        let before = vec![test("cap", "tests::it_works", TestStatus::Pass),
+                          test("smoke", "tests::it_works", TestStatus::Fail),
+                          test("smoke", "tests::other", TestStatus::Pass)];
+        let after = vec![test("cap", "tests::it_works", TestStatus::Pass),
+                         test("smoke", "tests::it_works", TestStatus::Fail),
+                         test("smoke", "tests::other", TestStatus::Fail)];
+        assert_eq!(newly_failing(&before, &after), vec!["smoke: tests::other"]);
+
+        let after = vec![test("cap", "tests::it_works", TestStatus::Fail)];
+        assert_eq!(newly_failing(&before, &after), vec!["cap: tests::it_works"]);
+    }
+}
@@ -4,7 +4,7 @@ Cargobomb works by serially processing a queue of commands, each of
 which transforms the application state in some discrete way, and
 designed to be resilient to I/O errors. The application state is
 backed by a directory in the filesystem, and optionally synchronized
-with s3.
+with s3 (see `push-ex` and `pull-ex`).
 
 These command queues may be created dynamically and executed in
 parallel jobs, either locally, or distributed on e.g. AWS. The
@@ -17,13 +17,16 @@ rewrite.
 
 */
 
+use cargobomb::agent;
 use cargobomb::docker;
 use cargobomb::errors::*;
 use cargobomb::ex;
 use cargobomb::ex::{ExCrate, ExCrateSelect, ExMode};
 use cargobomb::ex_run;
+use cargobomb::ex_sync;
 use cargobomb::lists;
-use cargobomb::report;
+use cargobomb::report::{self, ReportFormat, ReportOpts};
+use cargobomb::s3::S3Opts;
 use cargobomb::server;
 use cargobomb::toolchain::Toolchain;
 use std::path::PathBuf;
@@ -37,21 +40,27 @@ pub trait Cmd {
 }
 
 struct PrepareLocal;
-struct DefineEx(Ex, Toolchain, Toolchain, ExMode, ExCrateSelect);
+struct DefineEx(Ex, Toolchain, Toolchain, ExMode, ExCrateSelect, Vec<String>);
 struct PrepareEx(Ex);
 struct Run(Ex);
 struct RunTc(Ex, Toolchain);
-struct GenReport(Ex, PathBuf);
+struct GenReport(Ex, PathBuf, ReportOpts);
+struct GenIssues(Ex, Option<PathBuf>);
+struct DiffReports(Ex, Ex, PathBuf);
+struct PublishReport(Ex, PathBuf, S3Opts);
 struct DeleteAllTargetDirs(Ex);
 
 struct CreateLists;
 
 struct CopyEx(Ex, Ex);
 struct DeleteEx(Ex);
+struct PushEx(Ex, S3Opts);
+struct PullEx(Ex, S3Opts);
 
 struct DeleteAllResults(Ex);
 struct DeleteResult(Ex, Option<Toolchain>, ExCrate);
 struct Serve;
+struct Agent(String, String);
 
 
 // Local prep
@@ -74,33 +83,20 @@ impl Cmd for CreateLists {
 // Experiment prep
 impl Cmd for DefineEx {
     fn run(&self) -> Result<()> {
-        let &DefineEx(ref ex, ref tc1, ref tc2, ref mode, ref crates) = self;
+        let &DefineEx(ref ex, ref tc1, ref tc2, ref mode, ref crates, ref targets) = self;
         ex::define(ex::ExOpts {
                        name: ex.0.clone(),
                        toolchains: vec![tc1.clone(), tc2.clone()],
                        mode: mode.clone(),
                        crates: crates.clone(),
+                       targets: targets.clone(),
                    })
     }
 }
 impl Cmd for PrepareEx {
     fn run(&self) -> Result<()> {
         let &PrepareEx(ref ex) = self;
-        let ex = ex::Experiment::load(&ex.0)?;
-        // Shared experiment prep
-        ex.fetch_repo_crates()?;
-        ex::capture_shas(&ex)?;
-        ex::download_crates(&ex)?;
-        ex::frob_tomls(&ex)?;
-        ex::capture_lockfiles(&ex, &Toolchain::Dist("stable".into()), false)?;
-
-        // Local experiment prep
-        ex::delete_all_target_dirs(&ex.name)?;
-        ex_run::delete_all_results(&ex.name)?;
-        ex::fetch_deps(&ex, &Toolchain::Dist("stable".into()))?;
-        ex::prepare_all_toolchains(&ex)?;
-
-        Ok(())
+        ex::prepare(&ex::Experiment::load(&ex.0)?)
     }
 }
 impl Cmd for CopyEx {
@@ -153,18 +149,60 @@ impl Cmd for RunTc {
 // Reporting
 impl Cmd for GenReport {
     fn run(&self) -> Result<()> {
-        let &GenReport(ref ex, ref path) = self;
-        report::gen(&ex.0, path)
+        let &GenReport(ref ex, ref path, ref opts) = self;
+        report::gen(&ex.0, path, opts)
+    }
+}
+
+impl Cmd for DiffReports {
+    fn run(&self) -> Result<()> {
+        let &DiffReports(ref ex1, ref ex2, ref path) = self;
+        report::gen_diff(&ex1.0, &ex2.0, path)
+    }
+}
+
+impl Cmd for PublishReport {
+    fn run(&self) -> Result<()> {
+        let &PublishReport(ref ex, ref path, ref opts) = self;
+        report::publish_report(&ex.0, path, opts)
+    }
+}
+
+impl Cmd for GenIssues {
+    fn run(&self) -> Result<()> {
+        let &GenIssues(ref ex, ref template) = self;
+        report::gen_issues(&ex.0, template.as_ref().map(|p| &**p))
+    }
+}
+
+impl Cmd for PushEx {
+    fn run(&self) -> Result<()> {
+        let &PushEx(ref ex, ref opts) = self;
+        ex_sync::push(&ex.0, opts)
+    }
+}
+
+impl Cmd for PullEx {
+    fn run(&self) -> Result<()> {
+        let &PullEx(ref ex, ref opts) = self;
+        ex_sync::pull(&ex.0, opts)
     }
 }
 
 impl Cmd for Serve {
     fn run(&self) -> Result<()> {
-        server::start(server::Data);
+        server::start(server::Data::load()?);
         Ok(())
     }
 }
 
+impl Cmd for Agent {
+    fn run(&self) -> Result<()> {
+        let &Agent(ref server, ref name) = self;
+        agent::run(server, name)
+    }
+}
+
 // Boilerplate conversions on the model. Ideally all this would be generated.
 pub mod conv {
     use super::*;
@@ -190,19 +228,26 @@ pub mod conv {
                     ExMode::BuildOnly.to_str(),
                     ExMode::CheckOnly.to_str(),
                     ExMode::UnstableFeatures.to_str(),
+                    ExMode::CompileTime.to_str(),
+                    ExMode::Bench.to_str(),
                 ])
         };
         let crate_select = || {
             Arg::with_name("crate-select")
                 .required(false)
                 .long("crate-select")
-                .default_value(ExCrateSelect::Demo.to_str())
-                .possible_values(&[
-                    ExCrateSelect::Demo.to_str(),
-                    ExCrateSelect::Full.to_str(),
-                    ExCrateSelect::SmallRandom.to_str(),
-                    ExCrateSelect::Top100.to_str(),
-                ])
+                .default_value("demo")
+                .help("demo, full, small-random, top-100, or rdeps:<crate> for a crate and \
+                       its reverse dependencies")
+        };
+        let targets = || {
+            Arg::with_name("target")
+                .required(false)
+                .long("target")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("additionally build for a cross-compilation target")
         };
 
         fn opt(n: &'static str, def: &'static str) -> Arg<'static, 'static> {
@@ -217,6 +262,16 @@ pub mod conv {
             SubCommand::with_name(n).about(desc)
         }
 
+        fn s3_args(app: App<'static, 'static>) -> App<'static, 'static> {
+            app.arg(Arg::with_name("bucket")
+                        .long("bucket")
+                        .takes_value(true)
+                        .required(true))
+                .arg(opt("prefix", ""))
+                .arg(opt("endpoint", "https://s3.amazonaws.com"))
+                .arg(opt("region", "us-east-1"))
+        }
+
         vec![
             // Local prep
             cmd("prepare-local",
@@ -231,12 +286,18 @@ pub mod conv {
                 .arg(tc1())
                 .arg(tc2())
                 .arg(mode())
-                .arg(crate_select()),
+                .arg(crate_select())
+                .arg(targets()),
             cmd("prepare-ex", "prepare shared and local data for experiment").arg(ex()),
             cmd("copy-ex", "copy all data from one experiment to another")
                 .arg(ex1())
                 .arg(ex2()),
             cmd("delete-ex", "delete shared data for experiment").arg(ex()),
+            s3_args(cmd("push-ex", "upload shared data for experiment to S3-compatible storage")
+                        .arg(ex())),
+            s3_args(cmd("pull-ex",
+                        "download shared data for experiment from S3-compatible storage")
+                            .arg(ex())),
 
             cmd("delete-all-target-dirs",
                 "delete the cargo target dirs for an experiment")
@@ -261,9 +322,52 @@ pub mod conv {
             // Reporting
             cmd("gen-report", "generate the experiment report")
                 .arg(ex())
-                .arg(Arg::with_name("destination").required(true)),
+                .arg(Arg::with_name("destination").required(true))
+                .arg(Arg::with_name("format")
+                         .long("format")
+                         .default_value(ReportFormat::Html.to_str())
+                         .possible_values(&[ReportFormat::Html.to_str(),
+                                            ReportFormat::Markdown.to_str(),
+                                            ReportFormat::Text.to_str()])
+                         .help("also write a summary in this format"))
+                .arg(Arg::with_name("archive")
+                         .long("archive")
+                         .help("also pack the report into a .tar.gz next to it"))
+                .arg(Arg::with_name("log-cap")
+                         .long("log-cap")
+                         .takes_value(true)
+                         .required(false)
+                         .help("the maximum size of each log excerpt, in bytes"))
+                .arg(Arg::with_name("report-url")
+                         .long("report-url")
+                         .takes_value(true)
+                         .required(false)
+                         .help("where the report will be published, for links in the summary")),
+            s3_args(cmd("publish-report",
+                        "upload a generated report to S3-compatible storage")
+                            .arg(ex())
+                            .arg(Arg::with_name("report-dir").required(true))),
+            cmd("diff-reports",
+                "compare the regressions of an experiment with an earlier one")
+                    .arg(ex1())
+                    .arg(ex2())
+                    .arg(Arg::with_name("destination").required(true)),
+            cmd("gen-issues",
+                "write a markdown issue for each kind of regression")
+                    .arg(ex())
+                    .arg(Arg::with_name("template")
+                             .long("template")
+                             .takes_value(true)
+                             .required(false)
+                             .help("the issue template, instead of static/issue.md")),
 
             cmd("serve-report", "serve report"),
+            cmd("agent", "run crates handed out by a cargobomb server")
+                .arg(Arg::with_name("server")
+                         .long("server")
+                         .takes_value(true)
+                         .required(true))
+                .arg(opt("name", "agent")),
         ]
     }
 
@@ -303,6 +407,22 @@ pub mod conv {
                 .parse::<ExCrateSelect>()
         }
 
+        fn s3_opts(m: &ArgMatches) -> S3Opts {
+            let value = |name: &str| m.value_of(name).expect("").to_string();
+            S3Opts {
+                endpoint: value("endpoint"),
+                region: value("region"),
+                bucket: value("bucket"),
+                prefix: value("prefix"),
+            }
+        }
+
+        fn targets(m: &ArgMatches) -> Vec<String> {
+            m.values_of("target")
+                .map(|v| v.map(|t| t.to_string()).collect())
+                .unwrap_or_default()
+        }
+
         Ok(match m.subcommand() {
                // Local prep
                ("prepare-local", _) => Box::new(PrepareLocal),
@@ -310,11 +430,18 @@ pub mod conv {
 
                // Master experiment prep
                ("define-ex", Some(m)) => {
-                   Box::new(DefineEx(ex(m)?, tc1(m)?, tc2(m)?, mode(m)?, crate_select(m)?))
+                   Box::new(DefineEx(ex(m)?,
+                                     tc1(m)?,
+                                     tc2(m)?,
+                                     mode(m)?,
+                                     crate_select(m)?,
+                                     targets(m)))
                }
                ("prepare-ex", Some(m)) => Box::new(PrepareEx(ex(m)?)),
                ("copy-ex", Some(m)) => Box::new(CopyEx(ex1(m)?, ex2(m)?)),
                ("delete-ex", Some(m)) => Box::new(DeleteEx(ex(m)?)),
+               ("push-ex", Some(m)) => Box::new(PushEx(ex(m)?, s3_opts(m))),
+               ("pull-ex", Some(m)) => Box::new(PullEx(ex(m)?, s3_opts(m))),
 
                // Local experiment prep
                ("delete-all-target-dirs", Some(m)) => Box::new(DeleteAllTargetDirs(ex(m)?)),
@@ -332,11 +459,42 @@ pub mod conv {
 
                // Reporting
                ("gen-report", Some(m)) => {
+                   let log_cap = match m.value_of("log-cap") {
+                       Some(cap) => {
+                           cap.parse::<usize>()
+                               .chain_err(|| format!("invalid log cap: {}", cap))?
+                       }
+                       None => report::DEFAULT_LOG_CAP,
+                   };
                    Box::new(GenReport(ex(m)?,
-                                      m.value_of("destination").map(PathBuf::from).expect("")))
+                                      m.value_of("destination").map(PathBuf::from).expect(""),
+                                      ReportOpts {
+                                          format: m.value_of("format").expect("").parse()?,
+                                          archive: m.is_present("archive"),
+                                          log_cap: log_cap,
+                                          report_url: m.value_of("report-url").map(str::to_string),
+                                      }))
+               }
+
+               ("publish-report", Some(m)) => {
+                   Box::new(PublishReport(ex(m)?,
+                                          m.value_of("report-dir").map(PathBuf::from).expect(""),
+                                          s3_opts(m)))
+               }
+               ("diff-reports", Some(m)) => {
+                   Box::new(DiffReports(ex1(m)?,
+                                        ex2(m)?,
+                                        m.value_of("destination").map(PathBuf::from).expect("")))
+               }
+               ("gen-issues", Some(m)) => {
+                   Box::new(GenIssues(ex(m)?, m.value_of("template").map(PathBuf::from)))
                }
 
                ("serve-report", _) => Box::new(Serve),
+               ("agent", Some(m)) => {
+                   Box::new(Agent(m.value_of("server").expect("").to_string(),
+                                  m.value_of("name").expect("").to_string()))
+               }
 
                (s, _) => panic!("unimplemented args_to_cmd {}", s),
            })
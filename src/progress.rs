@@ -0,0 +1,181 @@
+// How far an experiment run has got, persisted so that it can be
+// followed through the server's API during long runs
+
+use chrono::UTC;
+use errors::*;
+use ex::{self, ExCrate, Experiment};
+use file;
+use results::{CrateResultWriter, ExperimentResultDB, FileDB, TestResult};
+use serde_json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use toolchain::Toolchain;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Progress {
+    /// Tests done and in total, by toolchain
+    pub toolchains: BTreeMap<String, TestCount>,
+    /// Tests done by result, with "error" for those that couldn't be run
+    pub results: BTreeMap<String, usize>,
+    /// The tests in progress
+    pub current: Vec<CurrentTest>,
+    /// The estimated time until every test is done
+    pub eta_secs: Option<u64>,
+    /// When this was last updated, in RFC 3339
+    pub updated: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TestCount {
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CurrentTest {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub toolchain: String,
+    /// The agent running the test, unless it's run locally
+    pub agent: Option<String>,
+    /// When the test started, in RFC 3339
+    pub started: String,
+}
+
+fn progress_file(ex_name: &str) -> PathBuf {
+    ex::ex_dir(ex_name).join("progress.json")
+}
+
+impl Progress {
+    /// Progress with no tests of `crates` crates against `toolchains` done
+    /// yet.
+    pub fn new(toolchains: &[Toolchain], crates: usize) -> Progress {
+        let mut progress = Progress::default();
+        for tc in toolchains {
+            progress
+                .toolchains
+                .insert(tc.to_string(), TestCount { done: 0, total: crates });
+        }
+        progress
+    }
+
+    /// Counts the results recorded for the experiment so far.
+    pub fn from_results(ex: &Experiment) -> Result<Progress> {
+        let crates = ex::ex_crates_and_dirs(ex)?
+            .into_iter()
+            .map(|(krate, _)| krate)
+            .collect::<Vec<_>>();
+        let db = FileDB::for_experiment(ex);
+        Progress::count(&ex.toolchains,
+                        &crates,
+                        |krate, tc| db.for_crate(krate, tc).load_test_result())
+    }
+
+    /// Counts the results of testing `crates` against `toolchains`, as
+    /// looked up by `result`.
+    fn count<F>(toolchains: &[Toolchain], crates: &[ExCrate], mut result: F) -> Result<Progress>
+        where F: FnMut(&ExCrate, &Toolchain) -> Result<Option<TestResult>>
+    {
+        let mut progress = Progress::new(toolchains, crates.len());
+        for krate in crates {
+            for tc in toolchains {
+                if let Some(r) = result(krate, tc)? {
+                    progress.record(tc, Some(&r));
+                }
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Counts a finished test, with `None` for tests that couldn't be run.
+    pub fn record(&mut self, toolchain: &Toolchain, result: Option<&TestResult>) {
+        if let Some(count) = self.toolchains.get_mut(&toolchain.to_string()) {
+            count.done += 1;
+        }
+        let result = match result {
+            Some(r) => r.to_string(),
+            None => "error".to_string(),
+        };
+        *self.results.entry(result).or_insert(0) += 1;
+    }
+
+    pub fn done(&self) -> usize {
+        self.toolchains.values().map(|c| c.done).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.toolchains.values().map(|c| c.total).sum()
+    }
+
+    pub fn load(ex_name: &str) -> Result<Option<Progress>> {
+        let path = progress_file(ex_name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&file::read_string(&path)?)?))
+    }
+
+    pub fn save(&mut self, ex_name: &str) -> Result<()> {
+        self.updated = UTC::now().to_rfc3339();
+        file::write_string(&progress_file(ex_name), &serde_json::to_string(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toolchains() -> Vec<Toolchain> {
+        vec![Toolchain::Dist("stable".into()), Toolchain::Dist("beta".into())]
+    }
+
+    #[test]
+    fn records_results() {
+        let mut progress = Progress::new(&toolchains(), 3);
+        assert_eq!(progress.done(), 0);
+        assert_eq!(progress.total(), 6);
+
+        progress.record(&Toolchain::Dist("stable".into()), Some(&TestResult::TestPass));
+        progress.record(&Toolchain::Dist("stable".into()), Some(&TestResult::TestPass));
+        progress.record(&Toolchain::Dist("beta".into()), None);
+        // Only counted by result
+        progress.record(&Toolchain::Dist("nightly".into()), Some(&TestResult::Ice));
+
+        assert_eq!(progress.done(), 3);
+        assert_eq!(progress.total(), 6);
+        assert_eq!(progress.toolchains["stable"].done, 2);
+        assert_eq!(progress.toolchains["beta"].done, 1);
+        assert_eq!(progress.results.get("test-pass"), Some(&2));
+        assert_eq!(progress.results.get("error"), Some(&1));
+        assert_eq!(progress.results.get("ice"), Some(&1));
+        assert_eq!(progress.results.get("build-fail"), None);
+    }
+
+    #[test]
+    fn counts_recorded_results() {
+        let ex_crate = |name: &str| {
+            ExCrate::Version {
+                name: name.to_string(),
+                version: "0.1.0".to_string(),
+            }
+        };
+        let crates = vec![ex_crate("foo"), ex_crate("bar")];
+
+        // Only foo has been tested
+        let progress = Progress::count(&toolchains(), &crates, |krate, tc| {
+            Ok(match (*krate == crates[0], &tc.to_string()[..]) {
+                   (true, "stable") => Some(TestResult::TestPass),
+                   (true, _) => Some(TestResult::BuildFail),
+                   (false, _) => None,
+               })
+        })
+                .unwrap();
+        assert_eq!(progress.done(), 2);
+        assert_eq!(progress.total(), 4);
+        assert_eq!(progress.results.get("test-pass"), Some(&1));
+        assert_eq!(progress.results.get("build-fail"), Some(&1));
+
+        let res = Progress::count(&toolchains(), &crates, |_, _| Err("unreadable".into()));
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// Comparison of the regressions of two experiments, e.g. last week's
+// nightly run and this week's, to find the new ones
+
+use super::{Comparison, CrateResult, generate_report};
+use errors::*;
+use ex;
+use file;
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportDiff {
+    ex_a: String,
+    ex_b: String,
+    /// Regressed in the second experiment but not the first
+    newly_regressed: Vec<DiffCrate>,
+    /// Regressed in both experiments
+    still_regressed: Vec<DiffCrate>,
+    /// Regressed in the first experiment, tested but not regressed in the
+    /// second
+    no_longer_regressed: Vec<DiffCrate>,
+    /// Regressed in the first experiment, but with an unknown result in the
+    /// second, or missing from it
+    #[serde(default)]
+    no_longer_tested: Vec<DiffCrate>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiffCrate {
+    name: String,
+    /// What went wrong in the later experiment, if it regressed there
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>,
+}
+
+impl DiffCrate {
+    fn new(c: &CrateResult) -> DiffCrate {
+        let error = match c.regressed_via {
+            Some(ref dep) => Some(format!("regressed via dep {}", dep)),
+            None => c.error.clone(),
+        };
+        DiffCrate {
+            name: c.name.clone(),
+            error: error,
+        }
+    }
+
+    fn untested(name: &str, why: &str) -> DiffCrate {
+        DiffCrate {
+            name: name.to_string(),
+            error: Some(why.to_string()),
+        }
+    }
+}
+
+fn is_regressed(c: &CrateResult) -> bool {
+    match c.res {
+        Comparison::Regressed => true,
+        _ => false,
+    }
+}
+
+/// Classifies the regressions of `ex_a` and `ex_b`. Crates are matched by
+/// name and version, so a regressed crate that was updated between the
+/// experiments counts as newly regressed.
+pub fn diff_reports(ex_a: &str, ex_b: &str) -> Result<ReportDiff> {
+    let res_a = generate_report(&ex::Experiment::load(ex_a)?, None)?;
+    let res_b = generate_report(&ex::Experiment::load(ex_b)?, None)?;
+
+    Ok(diff_crates(ex_a, ex_b, &res_a.crates, &res_b.crates))
+}
+
+/// Classifies the crates of the reports of `ex_a` and `ex_b`.
+fn diff_crates(ex_a: &str,
+               ex_b: &str,
+               crates_a: &[CrateResult],
+               crates_b: &[CrateResult])
+               -> ReportDiff {
+    let by_name_a = crates_a
+        .iter()
+        .map(|c| (&c.name[..], c))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut newly_regressed = Vec::new();
+    let mut still_regressed = Vec::new();
+    let mut no_longer_regressed = Vec::new();
+    let mut no_longer_tested = Vec::new();
+
+    for b in crates_b {
+        let was_regressed = by_name_a.get(&b.name[..]).map_or(false, |a| is_regressed(a));
+        match (was_regressed, &b.res) {
+            (false, &Comparison::Regressed) => newly_regressed.push(DiffCrate::new(b)),
+            (false, _) => (),
+            (true, &Comparison::Regressed) => still_regressed.push(DiffCrate::new(b)),
+            (true, &Comparison::Unknown) => {
+                no_longer_tested.push(DiffCrate::untested(&b.name, "unknown result"))
+            }
+            (true, _) => no_longer_regressed.push(DiffCrate::new(b)),
+        }
+    }
+
+    let names_b = crates_b
+        .iter()
+        .map(|c| &c.name[..])
+        .collect::<BTreeSet<_>>();
+    for a in crates_a.iter().filter(|a| is_regressed(a)) {
+        if !names_b.contains(&a.name[..]) {
+            no_longer_tested.push(DiffCrate::untested(&a.name, "not in the later experiment"));
+        }
+    }
+
+    ReportDiff {
+        ex_a: ex_a.to_string(),
+        ex_b: ex_b.to_string(),
+        newly_regressed: newly_regressed,
+        still_regressed: still_regressed,
+        no_longer_regressed: no_longer_regressed,
+        no_longer_tested: no_longer_tested,
+    }
+}
+
+/// Writes the diff of two experiments to `dest`, as diff.json and an HTML
+/// view of it.
+pub fn gen_diff(ex_a: &str, ex_b: &str, dest: &Path) -> Result<()> {
+    let diff = diff_reports(ex_a, ex_b)?;
+
+    fs::create_dir_all(dest)?;
+    let json_out = dest.join("diff.json");
+    info!("writing diff to {}", json_out.display());
+    file::write_string(&json_out, &serde_json::to_string(&diff)?)?;
+
+    let html_out = dest.join("index.html");
+    info!("writing diff report to {}", html_out.display());
+    file::write_string(&html_out, include_str!("../../static/diff.html"))?;
+    file::write_string(&dest.join("diff.js"), include_str!("../../static/diff.js"))?;
+    file::write_string(&dest.join("report.css"),
+                       include_str!("../../static/report.css"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use report::tests::crate_result;
+
+    fn names(crates: &[DiffCrate]) -> Vec<&str> {
+        crates.iter().map(|c| &c.name[..]).collect()
+    }
+
+    #[test]
+    fn classifies_regressions() {
+        let crates_a = vec![crate_result("still-0.1.0", Comparison::Regressed),
+                            crate_result("fixed-0.1.0", Comparison::Regressed),
+                            crate_result("unknown-0.1.0", Comparison::Regressed),
+                            crate_result("removed-0.1.0", Comparison::Regressed),
+                            crate_result("new-0.1.0", Comparison::SameTestPass),
+                            // Updated between the experiments
+                            crate_result("updated-0.1.0", Comparison::Regressed)];
+        let mut still = crate_result("still-0.1.0", Comparison::Regressed);
+        still.regressed_via = Some("dep 0.2.0".to_string());
+        let mut new = crate_result("new-0.1.0", Comparison::Regressed);
+        new.error = Some("E0277 in src/lib.rs:42".to_string());
+        let crates_b = vec![still,
+                            crate_result("fixed-0.1.0", Comparison::SameTestPass),
+                            crate_result("unknown-0.1.0", Comparison::Unknown),
+                            new,
+                            crate_result("updated-0.2.0", Comparison::Regressed),
+                            crate_result("passing-0.1.0", Comparison::SameTestPass),
+                            crate_result("broken-0.1.0", Comparison::SameBuildFail)];
+
+        let diff = diff_crates("ex-a", "ex-b", &crates_a, &crates_b);
+        assert_eq!(diff.ex_a, "ex-a");
+        assert_eq!(diff.ex_b, "ex-b");
+        assert_eq!(names(&diff.newly_regressed), vec!["new-0.1.0", "updated-0.2.0"]);
+        assert_eq!(diff.newly_regressed[0].error,
+                   Some("E0277 in src/lib.rs:42".to_string()));
+        assert_eq!(names(&diff.still_regressed), vec!["still-0.1.0"]);
+        assert_eq!(diff.still_regressed[0].error,
+                   Some("regressed via dep dep 0.2.0".to_string()));
+        assert_eq!(names(&diff.no_longer_regressed), vec!["fixed-0.1.0"]);
+        assert_eq!(names(&diff.no_longer_tested),
+                   vec!["unknown-0.1.0", "removed-0.1.0", "updated-0.1.0"]);
+        assert_eq!(diff.no_longer_tested[0].error, Some("unknown result".to_string()));
+        assert_eq!(diff.no_longer_tested[1].error,
+                   Some("not in the later experiment".to_string()));
+    }
+}
@@ -0,0 +1,220 @@
+// Markdown issues for regressions, in the format they're filed against
+// rust-lang/rust, rendered from a user-editable template
+
+use super::{CrateResult, crate_to_name, generate_report, read_log_string, tool_version};
+use clusters::Cluster;
+use errors::*;
+use ex::{self, ExCrate, ExMode, Experiment};
+use file;
+use gh_mirrors;
+use results::{ExperimentResultDB, FileDB, TestResult};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use util;
+
+// The number of log lines quoted when there's no compiler error to show
+const LOG_EXCERPT_LINES: usize = 20;
+// The number of backtrace frames quoted for internal compiler errors
+const BACKTRACE_EXCERPT_FRAMES: usize = 20;
+
+fn issues_dir(ex_name: &str) -> PathBuf {
+    ex::ex_dir(ex_name).join("issues")
+}
+
+/// Writes one issue per regression cluster to the experiment's `issues`
+/// directory, using the template at `template` or the default one. The
+/// first crate of each cluster is the one the issue describes.
+///
+/// Templates refer to variables as `{{name}}`; see `static/issue.md` for
+/// the available ones.
+pub fn gen_issues(ex_name: &str, template: Option<&Path>) -> Result<()> {
+    let ex = ex::Experiment::load(ex_name)?;
+    let template = match template {
+        Some(path) => {
+            file::read_string(path)
+                .chain_err(|| format!("unable to read template {}", path.display()))?
+        }
+        None => include_str!("../../static/issue.md").to_string(),
+    };
+
+    let results = generate_report(&ex, None)?;
+    let crates = results
+        .crates
+        .iter()
+        .map(|c| (c.name.clone(), c))
+        .collect::<BTreeMap<_, _>>();
+    let ex_crates = ex::ex_crates_and_dirs(&ex)?
+        .into_iter()
+        .filter_map(|(c, _)| crate_to_name(&c).ok().map(|name| (name, c)))
+        .collect::<BTreeMap<_, _>>();
+
+    let rustc_versions = ex.toolchains
+        .iter()
+        .map(|tc| tool_version(tc, tc.rustc_version()))
+        .collect::<Vec<_>>();
+
+    let dir = issues_dir(ex_name);
+    if dir.exists() {
+        util::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+
+    for (i, cluster) in results.clusters.iter().enumerate() {
+        let name = &cluster.crates[0];
+        let (res, krate) = match (crates.get(name), ex_crates.get(name)) {
+            (Some(res), Some(krate)) => (res, krate),
+            _ => {
+                error!("no results for crate {}", name);
+                continue;
+            }
+        };
+
+        let (crate_name, version, repro) = describe_crate(krate)?;
+        let subcommand = match res.runs[1] {
+            Some(ref r) => failing_subcommand(&ex.mode, &r.res),
+            None => "build",
+        };
+
+        let mut vars = vec![("crate", crate_name.clone()),
+                            ("version", version),
+                            ("toolchain_1", ex.toolchains[0].to_string()),
+                            ("toolchain_2", ex.toolchains[1].to_string()),
+                            ("rustc_1", rustc_versions[0].clone()),
+                            ("rustc_2", rustc_versions[1].clone()),
+                            ("command", format!("cargo {}", subcommand)),
+                            ("subcommand", subcommand.to_string()),
+                            ("error", error_excerpt(&ex, krate, res)),
+                            ("repro", repro)];
+        vars.extend(cluster_vars(cluster));
+
+        let path = dir.join(format!("{:03}-{}.md", i + 1, name));
+        info!("writing issue for {} to {}", name, path.display());
+        file::write_string(&path, &render(&template, &vars))?;
+    }
+
+    Ok(())
+}
+
+/// The template variables describing a cluster as a whole.
+fn cluster_vars(cluster: &Cluster) -> Vec<(&'static str, String)> {
+    let affected = cluster
+        .crates
+        .iter()
+        .map(|c| format!("- {}", c))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    vec![("signature", cluster.signature.clone()),
+         ("count", cluster.crates.len().to_string()),
+         ("crates", affected)]
+}
+
+fn render(template: &str, vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .fold(template.to_string(),
+              |s, &(name, ref value)| s.replace(&format!("{{{{{}}}}}", name), value))
+}
+
+/// The name, version and commands to fetch the source of a crate.
+fn describe_crate(krate: &ExCrate) -> Result<(String, String, String)> {
+    Ok(match *krate {
+           ExCrate::Version {
+               ref name,
+               ref version,
+           } => {
+               let repro = format!("curl -L https://crates.io/api/v1/crates/{0}/{1}/download \
+                                    | tar xz\ncd {0}-{1}",
+                                   name,
+                                   version);
+               (name.clone(), version.clone(), repro)
+           }
+           ExCrate::Repo { ref url, ref sha } => {
+               let (org, name) = gh_mirrors::gh_url_to_org_and_name(url)?;
+               let repro = format!("git clone {}\ncd {}\ngit checkout {}", url, name, sha);
+               (format!("{}/{}", org, name), sha.clone(), repro)
+           }
+       })
+}
+
+/// The cargo subcommand that failed with the second toolchain.
+fn failing_subcommand(mode: &ExMode, res: &TestResult) -> &'static str {
+    match (mode, res) {
+        (&ExMode::CheckOnly, _) => "check",
+        (&ExMode::Bench, &TestResult::TestFail) => "bench",
+        (_, &TestResult::TestFail) => "test",
+        _ => "build",
+    }
+}
+
+/// The key part of the failure: the backtrace of an internal compiler
+/// error, the first compiler error, or else the end of the log.
+fn error_excerpt(ex: &Experiment, krate: &ExCrate, res: &CrateResult) -> String {
+    if let Some(ref r) = res.runs[1] {
+        if let Some(ref ice) = r.ice {
+            let mut lines = vec![format!("error: internal compiler error: {}", ice.message)];
+            if let Some(ref location) = ice.location {
+                lines.push(format!("at {}", location));
+            }
+            lines.extend(ice.backtrace
+                             .iter()
+                             .take(BACKTRACE_EXCERPT_FRAMES)
+                             .cloned());
+            return lines.join("\n");
+        }
+
+        if let Some(d) = r.diagnostics.first() {
+            return match d.rendered {
+                       Some(ref rendered) => rendered.trim_right().to_string(),
+                       None => d.message.clone(),
+                   };
+        }
+    }
+
+    let db = FileDB::for_experiment(ex);
+    let log = read_log_string(&db.for_crate(krate, &ex.toolchains[1])).unwrap_or_default();
+    let lines = log.lines().collect::<Vec<_>>();
+    let start = lines.len().saturating_sub(LOG_EXCERPT_LINES);
+    lines[start..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clusters::SignatureKind;
+
+    #[test]
+    fn renders_the_default_template() {
+        let cluster = Cluster {
+            signature: "E0277: the trait bound `_` is not satisfied".to_string(),
+            kind: SignatureKind::Error,
+            crates: vec!["foo-0.1.0".to_string(), "bar-0.2.0".to_string()],
+        };
+        let mut vars = vec![("crate", "foo".to_string()),
+                            ("version", "0.1.0".to_string()),
+                            ("toolchain_1", "stable".to_string()),
+                            ("toolchain_2", "beta".to_string()),
+                            ("rustc_1", "rustc 1.18.0".to_string()),
+                            ("rustc_2", "rustc 1.19.0-beta.1".to_string()),
+                            ("command", "cargo build".to_string()),
+                            ("subcommand", "build".to_string()),
+                            ("error", "error[E0277]: oops".to_string()),
+                            ("repro", "cd foo-0.1.0".to_string())];
+        vars.extend(cluster_vars(&cluster));
+
+        let issue = render(include_str!("../../static/issue.md"), &vars);
+        assert!(!issue.contains("{{"), "unsubstituted variable in:\n{}", issue);
+        assert!(issue.starts_with("# Regression in foo: E0277: the trait bound `_` is not \
+                                   satisfied\n"));
+        assert!(issue.contains("foo 0.1.0 built with `stable` but fails with `beta`."));
+        assert!(issue.contains("cargo +beta build\n"));
+        assert!(issue.contains("This failure was seen in 2 crate(s):\n\n- foo-0.1.0\n- bar-0.2.0"));
+    }
+
+    #[test]
+    fn leaves_unknown_variables() {
+        let vars = [("count", "3".to_string())];
+        assert_eq!(render("{{count}} {{count}} {{missing}} {count}", &vars),
+                   "3 3 {{missing}} {count}");
+    }
+}
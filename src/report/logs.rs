@@ -0,0 +1,124 @@
+// Excerpts of build logs, so that reports stay small when crates produce
+// multi-megabyte logs
+
+use std::cmp;
+
+// The size of the log excerpts in reports, unless configured otherwise
+pub const DEFAULT_LOG_CAP: usize = 64 * 1024;
+
+// The number of lines kept from the start and end of a log
+const HEAD_LINES: usize = 50;
+const TAIL_LINES: usize = 100;
+// Room left for the notes marking where lines were cut
+const MARKER_BYTES: usize = 128;
+
+/// Cuts a log longer than `cap` bytes down to its first and last lines
+/// and the error lines between them, in at most `cap` bytes. A quarter of
+/// that goes to the head and half to the tail, which usually holds the
+/// failure.
+pub fn excerpt(log: &str, cap: usize) -> String {
+    if log.len() <= cap {
+        return log.to_string();
+    }
+
+    let cap = cap.saturating_sub(MARKER_BYTES);
+    let lines = log.lines().collect::<Vec<_>>();
+    let head_end = cmp::min(HEAD_LINES, lines.len());
+    let tail_start = cmp::max(head_end, lines.len().saturating_sub(TAIL_LINES));
+
+    let head = take_within(lines[..head_end].iter().cloned(), cap / 4);
+    let mut tail = take_within(lines[tail_start..].iter().rev().cloned(), cap / 2);
+    tail.reverse();
+    let used = head.iter().chain(&tail).map(|l| l.len() + 1).sum::<usize>();
+    let errors = take_within(lines[head.len()..lines.len() - tail.len()]
+                                 .iter()
+                                 .cloned()
+                                 .filter(|l| is_error_line(l)),
+                             cap.saturating_sub(used));
+
+    let omitted = lines.len() - head.len() - tail.len();
+    let mut out = head.join("\n");
+    out.push_str(&format!("\n[... {} lines omitted, {} error lines follow ...]\n",
+                          omitted,
+                          errors.len()));
+    for line in errors {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str("[... the full log is in log.txt.gz ...]\n");
+    out.push_str(&tail.join("\n"));
+    out.push('\n');
+    out
+}
+
+fn is_error_line(line: &str) -> bool {
+    line.contains("error") || line.contains("panicked at") || line.contains("FAILED")
+}
+
+fn take_within<'a, I>(lines: I, budget: usize) -> Vec<&'a str>
+    where I: Iterator<Item = &'a str>
+{
+    let mut used = 0;
+    lines
+        .take_while(|l| {
+                        used += l.len() + 1;
+                        used <= budget
+                    })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A build log with an error halfway through and a failure at the end
+    fn build_log() -> String {
+        let mut log = String::new();
+        for i in 0..1000 {
+            if i == 500 {
+                log.push_str("error[E0308]: mismatched types\n");
+            } else {
+                log.push_str(&format!("   Compiling dep-{} v0.1.0\n", i));
+            }
+        }
+        log.push_str("error: Could not compile `foo`.\n");
+        log
+    }
+
+    #[test]
+    fn keeps_short_logs() {
+        let log = build_log();
+        assert_eq!(excerpt(&log, log.len()), log);
+    }
+
+    #[test]
+    fn keeps_the_head_tail_and_errors() {
+        let log = build_log();
+        let excerpt = excerpt(&log, 8 * 1024);
+        let lines = excerpt.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "   Compiling dep-0 v0.1.0");
+        assert_eq!(lines[HEAD_LINES - 1], "   Compiling dep-49 v0.1.0");
+        assert_eq!(lines[HEAD_LINES], "[... 851 lines omitted, 1 error lines follow ...]");
+        assert_eq!(lines[HEAD_LINES + 1], "error[E0308]: mismatched types");
+        assert_eq!(lines[lines.len() - TAIL_LINES], "   Compiling dep-901 v0.1.0");
+        assert_eq!(lines[lines.len() - 1], "error: Could not compile `foo`.");
+        assert!(!excerpt.contains("dep-500 "));
+        assert!(!excerpt.contains("dep-900 "));
+    }
+
+    #[test]
+    fn respects_the_cap() {
+        let log = build_log();
+        for &cap in &[200, 1024, 4 * 1024, 8 * 1024] {
+            assert!(excerpt(&log, cap).len() <= cap);
+        }
+
+        // Error lines are only kept while they fit
+        let errors = (0..10000)
+            .map(|i| format!("error: failure {}\n", i))
+            .collect::<String>();
+        let excerpt = excerpt(&errors, 4 * 1024);
+        assert!(excerpt.len() <= 4 * 1024);
+        assert!(excerpt.len() > 3 * 1024);
+    }
+}
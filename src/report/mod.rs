@@ -0,0 +1,985 @@
+use clusters::{self, Cluster};
+use diagnostics::{self, Diagnostic};
+use docker::ResourceUsage;
+use errors::*;
+use ex;
+use file;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use gh_mirrors;
+use ice::Ice;
+use libtest::{self, BenchResult, TestCase};
+use results::{CrateResultWriter, CrateUsage, ExperimentResultDB, FileDB, TestResult};
+use serde_json;
+use std::{fs, io};
+use std::io::Read;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tar::Builder;
+use toolchain::Toolchain;
+use util;
+
+mod diff;
+mod issues;
+mod logs;
+mod publish;
+mod summary;
+
+pub use self::diff::gen_diff;
+pub use self::issues::gen_issues;
+pub use self::logs::DEFAULT_LOG_CAP;
+pub use self::publish::publish_report;
+pub use self::summary::{ReportFormat, comparison_counts};
+
+// Relative growth in build memory or CPU time between the two toolchains
+// above which a crate is flagged in the report
+const USAGE_GROWTH_THRESHOLD: f64 = 0.25;
+// Builds using less than this much memory or time are too noisy to flag
+const USAGE_MIN_RSS_KB: u64 = 64 * 1024;
+const USAGE_MIN_CPU_SECS: f64 = 5.0;
+// The number of slowdowns listed in compile-time reports
+const MAX_SLOWDOWNS: usize = 100;
+// Benchmarks that slowed down by less than this are not reported
+const BENCH_MIN_CHANGE: f64 = 0.05;
+
+fn results_file(dest: &Path) -> PathBuf {
+    dest.join("results.json")
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TestResults {
+    crates: Vec<CrateResult>,
+    /// The biggest compile-time slowdowns, largest first
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    slowdowns: Vec<Slowdown>,
+    /// Benchmarks that got significantly slower, largest first
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    bench_regressions: Vec<BenchRegression>,
+    /// Regressions grouped by the signature of their failure
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    clusters: Vec<Cluster>,
+    /// Every internal compiler error, whether or not it's a regression
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    ices: Vec<IceReport>,
+    /// The `rustc -V` and `cargo -V` of each toolchain
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    toolchain_versions: Vec<ToolchainVersion>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ToolchainVersion {
+    rustc: String,
+    cargo: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IceReport {
+    name: String,
+    toolchain: String,
+    log: String,
+    ice: Ice,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BenchRegression {
+    name: String,
+    bench: String,
+    before: f64,
+    after: f64,
+    change: f64,
+}
+
+/// A relative increase in a compile-time metric that exceeds the noise
+/// between repeated builds.
+#[derive(Serialize, Deserialize)]
+struct Slowdown {
+    name: String,
+    metric: String,
+    before: f64,
+    after: f64,
+    /// Relative change of the mean, e.g. 0.1 for 10% slower
+    change: f64,
+    /// Approximate 95% confidence bound of the relative change
+    noise: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CrateResult {
+    name: String,
+    res: Comparison,
+    runs: [Option<BuildTestResult>; 2],
+    /// Comparison of the two toolchains for each cross-compilation target
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    targets: BTreeMap<String, Comparison>,
+    /// Descriptions of excessive build memory or time growth
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    usage_growth: Vec<String>,
+    /// Tests that passed with the first toolchain and fail with the second,
+    /// as "<binary>: <name>"
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    failing_tests: Vec<String>,
+    /// The first compiler error of a regressed build, e.g.
+    /// "E0277 in src/lib.rs:42"
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>,
+    /// For regressions caused by a dependency failing to build, that
+    /// dependency, e.g. "foo 0.1.0"
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    regressed_via: Option<String>,
+    /// The regressed crates attributed to this crate, as their dependency
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    downstream: Vec<String>,
+    source: CrateSource,
+    /// The crate's page on crates.io, or its GitHub tree at the tested
+    /// commit
+    url: String,
+    /// The lockfile and frobbed Cargo.toml the crate was tested with,
+    /// relative to the report
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    lockfile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    froml: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum CrateSource {
+    Registry,
+    GitHub,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Comparison {
+    Regressed,
+    Fixed,
+    Unknown,
+    SameBuildFail,
+    SameTestFail,
+    SameTestPass,
+}
+
+impl Comparison {
+    /// The name of the comparison, as used by the HTML report.
+    fn to_str(&self) -> &'static str {
+        match *self {
+            Comparison::Regressed => "regressed",
+            Comparison::Fixed => "fixed",
+            Comparison::Unknown => "unknown",
+            Comparison::SameBuildFail => "same-build-fail",
+            Comparison::SameTestFail => "same-test-fail",
+            Comparison::SameTestPass => "same-test-pass",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BuildTestResult {
+    res: TestResult,
+    log: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    targets: BTreeMap<String, TestResult>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    usage: Option<CrateUsage>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    benches: Vec<BenchResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    tests: Vec<TestCase>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    diagnostics: Vec<Diagnostic>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    ice: Option<Ice>,
+}
+
+
+pub fn generate_report(ex: &ex::Experiment, dest: Option<&Path>) -> Result<TestResults> {
+    let db = FileDB::for_experiment(ex);
+    assert_eq!(ex.toolchains.len(), 2);
+
+    if let Some(dest) = dest {
+        fs::create_dir_all(dest)?;
+        let json = serde_json::to_string(&ex)?;
+        file::write_string(&dest.join("config.json"), &json)?;
+    }
+
+    let mut slowdowns = Vec::new();
+    let mut bench_regressions = Vec::new();
+    let mut signatures = Vec::new();
+    let mut ices = Vec::new();
+
+    let res = ex::ex_crates_and_dirs(ex)?
+        .into_iter()
+        .map(|(krate, _)| {
+            // Any errors here will turn into unknown results
+            let crate_results = ex.toolchains
+                .iter()
+                .map(|tc| load_build_test_result(&db.for_crate(&krate, tc)));
+            // Convert errors to Nones
+            let mut crate_results = crate_results.map(|r| r.ok()).collect::<Vec<_>>();
+            let crate2 = crate_results.pop().expect("");
+            let crate1 = crate_results.pop().expect("");
+            let comp = compare(&crate1, &crate2);
+            let targets = compare_targets(ex, &crate1, &crate2);
+            let usage_growth = compare_usage(&crate1, &crate2);
+            let name = crate_to_name(&krate).unwrap_or_else(|_| "<unknown>".into());
+            slowdowns.extend(compare_compile_times(&name, &crate1, &crate2));
+            bench_regressions.extend(compare_benches(&name, &crate1, &crate2));
+            let error = match (&comp, &crate2) {
+                (&Comparison::Regressed, &Some(ref r2)) => {
+                    r2.diagnostics.first().map(|d| d.summary())
+                }
+                _ => None,
+            };
+            let mut regressed_via = None;
+            if let (&Comparison::Regressed, &Some(ref r2)) = (&comp, &crate2) {
+                let log = read_log_string(&db.for_crate(&krate, &ex.toolchains[1]))
+                    .unwrap_or_default();
+                let crate_name = match krate {
+                    ex::ExCrate::Version { ref name, .. } => Some(&name[..]),
+                    ex::ExCrate::Repo { .. } => None,
+                };
+                regressed_via = diagnostics::failed_dependency(&r2.diagnostics, &log, crate_name);
+                let (kind, sig) = clusters::signature(&r2.diagnostics,
+                                                      r2.ice.as_ref(),
+                                                      &log,
+                                                      regressed_via.as_ref().map(|d| &d[..]));
+                signatures.push((name.clone(), kind, sig));
+            }
+            for (tc, r) in ex.toolchains.iter().zip(&[&crate1, &crate2]) {
+                if let Some(ref r) = **r {
+                    if let Some(ref ice) = r.ice {
+                        ices.push(IceReport {
+                                      name: name.clone(),
+                                      toolchain: tc.to_string(),
+                                      log: r.log.clone(),
+                                      ice: ice.clone(),
+                                  });
+                    }
+                }
+            }
+            let (source, url) = crate_source(&krate);
+            let (lockfile, froml) = match dest {
+                Some(dest) => {
+                    write_crate_files(ex, &krate, dest).unwrap_or_else(|e| {
+                        util::report_error(&e);
+                        (None, None)
+                    })
+                }
+                None => (None, None),
+            };
+            let failing_tests = match (&crate1, &crate2) {
+                (&Some(ref r1), &Some(ref r2)) => libtest::newly_failing(&r1.tests, &r2.tests),
+                _ => Vec::new(),
+            };
+
+            CrateResult {
+                name: name,
+                res: comp,
+                runs: [crate1, crate2],
+                targets: targets,
+                usage_growth: usage_growth,
+                failing_tests: failing_tests,
+                error: error,
+                regressed_via: regressed_via,
+                downstream: Vec::new(),
+                source: source,
+                url: url,
+                lockfile: lockfile,
+                froml: froml,
+            }
+        })
+        .collect::<Vec<_>>();
+    let res = attribute_to_dependencies(res);
+
+    let slowdowns = worst_slowdowns(slowdowns);
+    bench_regressions.sort_by(|a, b| {
+                                  b.change
+                                      .partial_cmp(&a.change)
+                                      .unwrap_or(Ordering::Equal)
+                              });
+
+    Ok(TestResults {
+           crates: res,
+           slowdowns: slowdowns,
+           bench_regressions: bench_regressions,
+           clusters: clusters::cluster(signatures),
+           ices: ices,
+           toolchain_versions: Vec::new(),
+       })
+}
+
+
+pub struct ReportOpts {
+    pub format: ReportFormat,
+    /// Whether to also pack the report into a .tar.gz
+    pub archive: bool,
+    /// The maximum size of each log excerpt, in bytes
+    pub log_cap: usize,
+    /// Where the report will be published, for absolute links in the
+    /// summary
+    pub report_url: Option<String>,
+}
+
+pub fn gen(ex_name: &str, dest: &Path, opts: &ReportOpts) -> Result<()> {
+    let ex = ex::Experiment::load(ex_name)?;
+
+    let mut res = generate_report(&ex, Some(dest))?;
+    res.toolchain_versions = ex.toolchains
+        .iter()
+        .map(|tc| {
+                 ToolchainVersion {
+                     rustc: tool_version(tc, tc.rustc_version()),
+                     cargo: tool_version(tc, tc.cargo_version()),
+                 }
+             })
+        .collect();
+    let json = serde_json::to_string(&res)?;
+
+    info!("writing results to {}", results_file(dest).display());
+    file::write_string(&results_file(dest), &json)?;
+
+    write_logs(&ex, dest, opts.log_cap)?;
+    write_html_files(dest)?;
+    summary::write_summary(&ex,
+                           &res,
+                           dest,
+                           opts.format,
+                           opts.report_url.as_ref().map(|u| &u[..]))?;
+
+    if opts.archive {
+        write_archive(dest)?;
+    }
+
+    Ok(())
+}
+
+/// Attributes regressions caused by a dependency to that dependency's own
+/// entry in the report, when it's part of the experiment.
+fn attribute_to_dependencies(mut crates: Vec<CrateResult>) -> Vec<CrateResult> {
+    let mut downstream: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for c in &crates {
+        if let Some(ref dep) = c.regressed_via {
+            // "foo 0.1.0" is named "foo-0.1.0" in the report
+            let dep_name = dep.replace(' ', "-");
+            downstream
+                .entry(dep_name)
+                .or_insert_with(Vec::new)
+                .push(c.name.clone());
+        }
+    }
+
+    for c in &mut crates {
+        if let Some(names) = downstream.remove(&c.name) {
+            c.downstream = names;
+        }
+    }
+
+    crates
+}
+
+/// A toolchain's version, or "unknown" if it couldn't be found.
+fn tool_version(tc: &Toolchain, version: Result<String>) -> String {
+    version.unwrap_or_else(|e| {
+                               error!("unable to get version of {}: {}", tc.to_string(), e);
+                               "unknown".to_string()
+                           })
+}
+
+fn crate_source(c: &ex::ExCrate) -> (CrateSource, String) {
+    match *c {
+        ex::ExCrate::Version {
+            ref name,
+            ref version,
+        } => {
+            (CrateSource::Registry, format!("https://crates.io/crates/{}/{}", name, version))
+        }
+        ex::ExCrate::Repo { ref url, ref sha } => {
+            (CrateSource::GitHub, format!("{}/tree/{}", url, sha))
+        }
+    }
+}
+
+/// Copies the lockfile and frobbed Cargo.toml a crate was tested with into
+/// the report, returning their paths relative to it.
+fn write_crate_files(ex: &ex::Experiment,
+                     krate: &ex::ExCrate,
+                     dest: &Path)
+                     -> Result<(Option<String>, Option<String>)> {
+    let (name, version) = match *krate {
+        ex::ExCrate::Version {
+            ref name,
+            ref version,
+        } => (name, version),
+        ex::ExCrate::Repo { .. } => return Ok((None, None)),
+    };
+
+    let copy = |src: &Path, rel: String| -> Result<Option<String>> {
+        if !src.exists() {
+            return Ok(None);
+        }
+        let out = dest.join(&rel);
+        fs::create_dir_all(out.parent().expect(""))?;
+        fs::copy(src, &out)?;
+        Ok(Some(rel))
+    };
+
+    let lockfile = copy(&ex::lockfile(&ex.name, krate)?,
+                        format!("lockfiles/{}-{}.lock", name, version))?;
+    let froml = copy(&ex::froml_path(&ex.name, name, version),
+                     format!("fromls/{}-{}.Cargo.toml", name, version))?;
+    Ok((lockfile, froml))
+}
+
+/// Packs the report into a single `.tar.gz` next to it, so it can be
+/// attached to an issue or uploaded as one file.
+fn write_archive(dest: &Path) -> Result<()> {
+    let dir_name = match dest.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => bail!("report destination {} has no name", dest.display()),
+    };
+    let archive = dest.with_file_name(format!("{}.tar.gz", dir_name));
+    info!("writing report archive to {}", archive.display());
+
+    let encoder = GzEncoder::new(File::create(&archive)?, Compression::Default);
+    let mut builder = Builder::new(encoder);
+    builder.append_dir_all(&dir_name, dest)?;
+    builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+fn load_build_test_result<W: CrateResultWriter>(writer: &W) -> Result<BuildTestResult> {
+    // If there was no test result return an error
+    let res = writer
+        .load_test_result()?
+        .ok_or_else(|| Error::from("no result"))?;
+
+    Ok(BuildTestResult {
+           res: res,
+           log: format!("{}", writer.result_path_fragement().display()),
+           targets: writer.load_target_results()?,
+           usage: writer.load_usage()?,
+           benches: writer.load_benches()?,
+           tests: writer.load_tests()?,
+           diagnostics: writer.load_diagnostics()?,
+           ice: writer.load_ice()?,
+       })
+}
+
+/// Finds the crate of an experiment named as in its report.
+pub fn find_crate(ex: &ex::Experiment, name: &str) -> Result<ex::ExCrate> {
+    for (krate, _) in ex::ex_crates_and_dirs(ex)? {
+        if crate_to_name(&krate).ok().map_or(false, |n| n == name) {
+            return Ok(krate);
+        }
+    }
+    bail!(ErrorKind::NotFound(format!("crate {} in experiment {}", name, ex.name)))
+}
+
+/// Finds the toolchain of an experiment by its name.
+pub fn find_toolchain(ex: &ex::Experiment, name: &str) -> Result<Toolchain> {
+    match ex.toolchains.iter().find(|tc| tc.to_string() == name) {
+        Some(tc) => Ok(tc.clone()),
+        None => {
+            bail!(ErrorKind::NotFound(format!("toolchain {} in experiment {}", name, ex.name)))
+        }
+    }
+}
+
+/// The result of testing one crate of an experiment, as in its report.
+pub fn crate_result(ex: &ex::Experiment,
+                    krate: &ex::ExCrate,
+                    tc: &Toolchain)
+                    -> Result<BuildTestResult> {
+    load_build_test_result(&FileDB::for_experiment(ex).for_crate(krate, tc))
+}
+
+fn read_log_string<W: CrateResultWriter>(writer: &W) -> Result<String> {
+    let mut log = String::new();
+    writer.read_log()?.read_to_string(&mut log)?;
+    Ok(log)
+}
+
+fn crate_to_name(c: &ex::ExCrate) -> Result<String> {
+    match *c {
+        ex::ExCrate::Version {
+            ref name,
+            ref version,
+        } => Ok(format!("{}-{}", name, version)),
+        ex::ExCrate::Repo { ref url, ref sha } => {
+            let (org, name) = gh_mirrors::gh_url_to_org_and_name(url)?;
+            Ok(format!("{}.{}.{}", org, name, sha))
+        }
+    }
+}
+
+fn compare(r1: &Option<BuildTestResult>, r2: &Option<BuildTestResult>) -> Comparison {
+    match (r1, r2) {
+        (&Some(BuildTestResult { res: ref res1, .. }),
+         &Some(BuildTestResult { res: ref res2, .. })) => compare_results(res1, res2),
+        _ => Comparison::Unknown,
+    }
+}
+
+fn compare_results(res1: &TestResult, res2: &TestResult) -> Comparison {
+    use results::TestResult::*;
+    match (res1, res2) {
+        (&BuildFail, &BuildFail) |
+        (&Ice, &Ice) => Comparison::SameBuildFail,
+        (&TestFail, &TestFail) => Comparison::SameTestFail,
+        (&TestPass, &TestPass) => Comparison::SameTestPass,
+        (&BuildFail, &TestFail) |
+        (&BuildFail, &TestPass) |
+        (&TestFail, &TestPass) |
+        (&Ice, &BuildFail) |
+        (&Ice, &TestFail) |
+        (&Ice, &TestPass) => Comparison::Fixed,
+        (&TestPass, &TestFail) |
+        (&TestPass, &BuildFail) |
+        (&TestFail, &BuildFail) |
+        (&BuildFail, &Ice) |
+        (&TestFail, &Ice) |
+        (&TestPass, &Ice) => Comparison::Regressed,
+    }
+}
+
+/// Builds this crate's row of the crate-platform compatibility matrix.
+fn compare_targets(ex: &ex::Experiment,
+                   r1: &Option<BuildTestResult>,
+                   r2: &Option<BuildTestResult>)
+                   -> BTreeMap<String, Comparison> {
+    ex.targets
+        .iter()
+        .map(|target| {
+            let res1 = r1.as_ref().and_then(|r| r.targets.get(target));
+            let res2 = r2.as_ref().and_then(|r| r.targets.get(target));
+            let comp = match (res1, res2) {
+                (Some(res1), Some(res2)) => compare_results(res1, res2),
+                _ => Comparison::Unknown,
+            };
+            (target.to_string(), comp)
+        })
+        .collect()
+}
+
+/// Flags builds whose peak memory or CPU time grew by more than
+/// `USAGE_GROWTH_THRESHOLD` from the first toolchain to the second.
+fn compare_usage(r1: &Option<BuildTestResult>, r2: &Option<BuildTestResult>) -> Vec<String> {
+    let build_usage = |r: &Option<BuildTestResult>| {
+        r.as_ref()
+            .and_then(|r| r.usage.as_ref())
+            .and_then(|u| u.build)
+    };
+    let (u1, u2) = match (build_usage(r1), build_usage(r2)) {
+        (Some(u1), Some(u2)) => (u1, u2),
+        _ => return Vec::new(),
+    };
+
+    let mut growth = Vec::new();
+    if u2.max_rss_kb >= USAGE_MIN_RSS_KB {
+        let rss1 = u1.max_rss_kb as f64;
+        let rss2 = u2.max_rss_kb as f64;
+        if rss1 > 0.0 && (rss2 / rss1 - 1.0) > USAGE_GROWTH_THRESHOLD {
+            growth.push(format!("build memory +{:.0}%", (rss2 / rss1 - 1.0) * 100.0));
+        }
+    }
+    if u2.cpu_secs() >= USAGE_MIN_CPU_SECS {
+        let cpu1 = u1.cpu_secs();
+        let cpu2 = u2.cpu_secs();
+        if cpu1 > 0.0 && (cpu2 / cpu1 - 1.0) > USAGE_GROWTH_THRESHOLD {
+            growth.push(format!("build time +{:.0}%", (cpu2 / cpu1 - 1.0) * 100.0));
+        }
+    }
+
+    growth
+}
+
+/// Compares the clean-build samples of a compile-time experiment,
+/// returning the metrics that got slower by more than the noise.
+fn compare_compile_times(name: &str,
+                         r1: &Option<BuildTestResult>,
+                         r2: &Option<BuildTestResult>)
+                         -> Vec<Slowdown> {
+    let samples = |r: &Option<BuildTestResult>| {
+        r.as_ref()
+            .and_then(|r| r.usage.as_ref())
+            .map(|u| u.compile_samples.clone())
+            .unwrap_or_default()
+    };
+    let (s1, s2) = (samples(r1), samples(r2));
+    if s1.is_empty() || s2.is_empty() {
+        return Vec::new();
+    }
+
+    let wall1 = s1.iter().map(|u| u.wall_secs).collect::<Vec<_>>();
+    let wall2 = s2.iter().map(|u| u.wall_secs).collect::<Vec<_>>();
+    let mut metrics = vec![("wall-time", wall1, wall2)];
+
+    let instructions = |s: &[ResourceUsage]| {
+        s.iter()
+            .map(|u| u.instructions.map(|i| i as f64))
+            .collect::<Option<Vec<_>>>()
+    };
+    if let (Some(i1), Some(i2)) = (instructions(&s1), instructions(&s2)) {
+        metrics.push(("instructions", i1, i2));
+    }
+
+    metrics
+        .into_iter()
+        .filter_map(|(metric, v1, v2)| {
+            let (mean1, sd1) = mean_and_stddev(&v1);
+            let (mean2, sd2) = mean_and_stddev(&v2);
+            if mean1 <= 0.0 {
+                return None;
+            }
+            let se1 = sd1 / (v1.len() as f64).sqrt();
+            let se2 = sd2 / (v2.len() as f64).sqrt();
+            let change = mean2 / mean1 - 1.0;
+            let noise = 2.0 * (se1 * se1 + se2 * se2).sqrt() / mean1;
+            if change > noise {
+                Some(Slowdown {
+                         name: name.to_string(),
+                         metric: metric.to_string(),
+                         before: mean1,
+                         after: mean2,
+                         change: change,
+                         noise: noise,
+                     })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Sorts slowdowns largest first, keeping the first `MAX_SLOWDOWNS`.
+fn worst_slowdowns(mut slowdowns: Vec<Slowdown>) -> Vec<Slowdown> {
+    slowdowns.sort_by(|a, b| {
+                          b.change
+                              .partial_cmp(&a.change)
+                              .unwrap_or(Ordering::Equal)
+                      });
+    slowdowns.truncate(MAX_SLOWDOWNS);
+    slowdowns
+}
+
+/// Finds the benchmarks that got slower by more than their combined
+/// deviation, and by at least `BENCH_MIN_CHANGE`.
+fn compare_benches(name: &str,
+                   r1: &Option<BuildTestResult>,
+                   r2: &Option<BuildTestResult>)
+                   -> Vec<BenchRegression> {
+    let (b1, b2) = match (r1, r2) {
+        (&Some(ref r1), &Some(ref r2)) => (&r1.benches, &r2.benches),
+        _ => return Vec::new(),
+    };
+
+    b2.iter()
+        .filter_map(|after| {
+            let before = match b1.iter().find(|b| b.name == after.name) {
+                Some(before) => before,
+                None => return None,
+            };
+            if before.ns_per_iter <= 0.0 {
+                return None;
+            }
+            let change = after.ns_per_iter / before.ns_per_iter - 1.0;
+            let delta = after.ns_per_iter - before.ns_per_iter;
+            if change >= BENCH_MIN_CHANGE && delta > before.deviation + after.deviation {
+                Some(BenchRegression {
+                         name: name.to_string(),
+                         bench: after.name.clone(),
+                         before: before.ns_per_iter,
+                         after: after.ns_per_iter,
+                         change: change,
+                     })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn mean_and_stddev(v: &[f64]) -> (f64, f64) {
+    let n = v.len() as f64;
+    let mean = v.iter().sum::<f64>() / n;
+    if v.len() < 2 {
+        return (mean, 0.0);
+    }
+    let var = v.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (n - 1.0);
+    (mean, var.sqrt())
+}
+
+fn write_html_files(dir: &Path) -> Result<()> {
+    let html_in = include_str!("../../static/report.html");
+    let js_in = include_str!("../../static/report.js");
+    let css_in = include_str!("../../static/report.css");
+    let html_out = dir.join("index.html");
+    let js_out = dir.join("report.js");
+    let css_out = dir.join("report.css");
+
+    info!("writing report to {}", html_out.display());
+
+    file::write_string(&html_out, html_in)?;
+    file::write_string(&js_out, js_in)?;
+    file::write_string(&css_out, css_in)?;
+
+    Ok(())
+}
+
+/// Writes an excerpt of each log, of at most `log_cap` bytes, to the
+/// report as log.txt, with the full log next to it as log.txt.gz.
+fn write_logs(ex: &ex::Experiment, dest: &Path, log_cap: usize) -> Result<()> {
+    let db = FileDB::for_experiment(ex);
+    for (krate, _) in ex::ex_crates_and_dirs(ex)? {
+        for tc in &ex.toolchains {
+            let writer = db.for_crate(&krate, tc);
+            if writer.load_test_result()?.is_none() {
+                continue;
+            }
+            if let Err(e) = write_log_files(dest, &writer, log_cap) {
+                util::report_error(&e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_log_files<W: CrateResultWriter>(dest: &Path, writer: &W, log_cap: usize) -> Result<()> {
+    let log_dir = dest.join(writer.result_path_fragement());
+    fs::create_dir_all(&log_dir)?;
+
+    let log = read_log_string(writer)?;
+    file::write_string(&log_dir.join("log.txt"), &logs::excerpt(&log, log_cap))?;
+    io::copy(&mut writer.read_compressed_log()?,
+             &mut File::create(log_dir.join("log.txt.gz"))?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn build_result() -> BuildTestResult {
+        BuildTestResult {
+            res: TestResult::TestPass,
+            log: String::new(),
+            targets: BTreeMap::new(),
+            usage: None,
+            benches: Vec::new(),
+            tests: Vec::new(),
+            diagnostics: Vec::new(),
+            ice: None,
+        }
+    }
+
+    fn usage(max_rss_kb: u64, cpu_secs: f64) -> ResourceUsage {
+        ResourceUsage {
+            max_rss_kb: max_rss_kb,
+            user_secs: cpu_secs,
+            sys_secs: 0.0,
+            wall_secs: cpu_secs,
+            instructions: None,
+        }
+    }
+
+    fn with_build_usage(build: Option<ResourceUsage>) -> Option<BuildTestResult> {
+        let mut r = build_result();
+        r.usage = Some(CrateUsage {
+                           build: build,
+                           ..CrateUsage::default()
+                       });
+        Some(r)
+    }
+
+    #[test]
+    fn flags_usage_growth_above_the_threshold() {
+        // Memory grew by 50%, time by only 10%
+        let r1 = with_build_usage(Some(usage(100 * 1024, 10.0)));
+        let r2 = with_build_usage(Some(usage(150 * 1024, 11.0)));
+        assert_eq!(compare_usage(&r1, &r2), vec!["build memory +50%".to_string()]);
+
+        let r2 = with_build_usage(Some(usage(110 * 1024, 20.0)));
+        assert_eq!(compare_usage(&r1, &r2), vec!["build time +100%".to_string()]);
+
+        // Shrinking is never flagged
+        assert!(compare_usage(&r2, &r1).is_empty());
+    }
+
+    #[test]
+    fn ignores_small_builds_and_missing_usage() {
+        // Tripled, but below the minimums
+        let r1 = with_build_usage(Some(usage(1024, 1.0)));
+        let r2 = with_build_usage(Some(usage(3 * 1024, 3.0)));
+        assert!(compare_usage(&r1, &r2).is_empty());
+
+        let big = with_build_usage(Some(usage(500 * 1024, 100.0)));
+        assert!(compare_usage(&with_build_usage(None), &big).is_empty());
+        assert!(compare_usage(&Some(build_result()), &big).is_empty());
+        assert!(compare_usage(&None, &big).is_empty());
+        assert!(compare_usage(&r1, &with_build_usage(None)).is_empty());
+    }
+
+    fn sample(wall_secs: f64, instructions: Option<u64>) -> ResourceUsage {
+        ResourceUsage {
+            instructions: instructions,
+            ..usage(100 * 1024, wall_secs)
+        }
+    }
+
+    fn with_samples(samples: Vec<ResourceUsage>) -> Option<BuildTestResult> {
+        let mut r = build_result();
+        r.usage = Some(CrateUsage {
+                           compile_samples: samples,
+                           ..CrateUsage::default()
+                       });
+        Some(r)
+    }
+
+    #[test]
+    fn reports_slowdowns_beyond_the_noise() {
+        let before = with_samples(vec![sample(10.0, None), sample(10.2, None), sample(9.8, None)]);
+        // 1% slower, within the noise of the samples
+        let noisy = with_samples(vec![sample(10.1, None), sample(10.3, None), sample(9.9, None)]);
+        assert!(compare_compile_times("foo-0.1.0", &before, &noisy).is_empty());
+
+        // 20% slower
+        let slower = with_samples(vec![sample(12.0, None), sample(12.2, None), sample(11.8, None)]);
+        let slowdowns = compare_compile_times("foo-0.1.0", &before, &slower);
+        assert_eq!(slowdowns.len(), 1);
+        assert_eq!(slowdowns[0].name, "foo-0.1.0");
+        assert_eq!(slowdowns[0].metric, "wall-time");
+        assert!((slowdowns[0].change - 0.2).abs() < 1e-9);
+        assert!(slowdowns[0].noise > 0.0 && slowdowns[0].noise < 0.2);
+
+        assert!(compare_compile_times("foo-0.1.0", &before, &with_samples(Vec::new())).is_empty());
+        assert!(compare_compile_times("foo-0.1.0", &None, &slower).is_empty());
+    }
+
+    #[test]
+    fn compares_instructions_when_both_sides_counted_them() {
+        let before = with_samples(vec![sample(10.0, Some(1000)), sample(10.0, Some(1010))]);
+        let after = with_samples(vec![sample(10.0, Some(1500)), sample(10.0, Some(1510))]);
+        let slowdowns = compare_compile_times("foo-0.1.0", &before, &after);
+        assert_eq!(slowdowns.len(), 1);
+        assert_eq!(slowdowns[0].metric, "instructions");
+
+        // One build of the second toolchain wasn't counted
+        let after = with_samples(vec![sample(10.0, Some(1500)), sample(10.0, None)]);
+        assert!(compare_compile_times("foo-0.1.0", &before, &after).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_worst_slowdowns() {
+        let slowdowns = (0..MAX_SLOWDOWNS + 10)
+            .map(|i| {
+                     Slowdown {
+                         name: format!("crate-{}", i),
+                         metric: "wall-time".to_string(),
+                         before: 1.0,
+                         after: 1.0,
+                         change: i as f64 / 100.0,
+                         noise: 0.0,
+                     }
+                 })
+            .collect();
+        let worst = worst_slowdowns(slowdowns);
+        assert_eq!(worst.len(), MAX_SLOWDOWNS);
+        assert_eq!(worst[0].name, format!("crate-{}", MAX_SLOWDOWNS + 9));
+        assert_eq!(worst[MAX_SLOWDOWNS - 1].name, "crate-10");
+        assert!(worst.windows(2).all(|w| w[0].change >= w[1].change));
+    }
+
+    fn with_benches(benches: &[(&str, f64, f64)]) -> Option<BuildTestResult> {
+        let mut r = build_result();
+        r.benches = benches
+            .iter()
+            .map(|&(name, ns_per_iter, deviation)| {
+                     BenchResult {
+                         name: name.to_string(),
+                         ns_per_iter: ns_per_iter,
+                         deviation: deviation,
+                     }
+                 })
+            .collect();
+        Some(r)
+    }
+
+    #[test]
+    fn reports_significant_bench_regressions() {
+        let before = with_benches(&[("fast", 100.0, 1.0),
+                                    ("noisy", 100.0, 20.0),
+                                    ("tiny", 1000.0, 1.0),
+                                    ("slow", 100.0, 1.0),
+                                    ("gone", 100.0, 1.0)]);
+        let after = with_benches(&[("fast", 50.0, 1.0),
+                                   // 30% slower, but within the deviations
+                                   ("noisy", 130.0, 20.0),
+                                   // Outside the deviations, but under BENCH_MIN_CHANGE
+                                   ("tiny", 1010.0, 1.0),
+                                   ("slow", 150.0, 2.0),
+                                   // Only run by the second toolchain
+                                   ("new", 500.0, 1.0)]);
+        let regressions = compare_benches("foo-0.1.0", &before, &after);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].name, "foo-0.1.0");
+        assert_eq!(regressions[0].bench, "slow");
+        assert_eq!(regressions[0].before, 100.0);
+        assert_eq!(regressions[0].after, 150.0);
+        assert!((regressions[0].change - 0.5).abs() < 1e-9);
+
+        // Going the other way, only the faster benchmark regressed
+        let regressions = compare_benches("foo-0.1.0", &after, &before);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].bench, "fast");
+
+        assert!(compare_benches("foo-0.1.0", &before, &None).is_empty());
+    }
+
+    pub fn crate_result(name: &str, res: Comparison) -> CrateResult {
+        CrateResult {
+            name: name.to_string(),
+            res: res,
+            runs: [None, None],
+            targets: BTreeMap::new(),
+            usage_growth: Vec::new(),
+            failing_tests: Vec::new(),
+            error: None,
+            regressed_via: None,
+            downstream: Vec::new(),
+            source: CrateSource::Registry,
+            url: String::new(),
+            lockfile: None,
+            froml: None,
+        }
+    }
+
+    #[test]
+    fn attributes_regressions_to_their_dependencies() {
+        let regressed_via = |name: &str, dep: &str| {
+            let mut c = crate_result(name, Comparison::Regressed);
+            c.regressed_via = Some(dep.to_string());
+            c
+        };
+        let crates = vec![regressed_via("app-1.0.0", "dep 0.1.0"),
+                          crate_result("dep-0.1.0", Comparison::Regressed),
+                          regressed_via("cli-2.0.0", "dep 0.1.0"),
+                          crate_result("other-0.3.0", Comparison::Fixed),
+                          // "missing" isn't part of the experiment
+                          regressed_via("tool-0.1.0", "missing 1.0.0")];
+        let crates = attribute_to_dependencies(crates);
+        let downstream = crates
+            .iter()
+            .map(|c| (&c.name[..], c.downstream.clone()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(downstream["dep-0.1.0"],
+                   vec!["app-1.0.0".to_string(), "cli-2.0.0".to_string()]);
+        assert!(downstream["app-1.0.0"].is_empty());
+        assert!(downstream["cli-2.0.0"].is_empty());
+        assert!(downstream["other-0.3.0"].is_empty());
+        assert!(downstream["tool-0.1.0"].is_empty());
+        assert_eq!(crates.len(), 5);
+    }
+}
@@ -0,0 +1,136 @@
+// Uploading of generated reports to S3-compatible object storage
+
+use chrono::UTC;
+use errors::*;
+use s3::{self, S3Client, S3Opts, key};
+use serde_json;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// An experiment listed on the index page.
+#[derive(Serialize, Deserialize)]
+struct PublishedReport {
+    name: String,
+    /// When the report was last published, in RFC 3339
+    published: String,
+}
+
+/// Uploads the report that `gen-report` wrote to `report_dir`, under
+/// `<prefix>/<ex_name>/`, skipping files whose contents are already there.
+/// The index page at `<prefix>/index.html` is updated to list the
+/// experiment.
+pub fn publish_report(ex_name: &str, report_dir: &Path, opts: &S3Opts) -> Result<()> {
+    let client = opts.connect()?;
+    let base = key(&opts.prefix, ex_name);
+
+    let mut uploaded = 0;
+    let mut skipped = 0;
+    for entry in WalkDir::new(report_dir) {
+        let entry = entry.chain_err(|| "walk dir")?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(report_dir).expect("");
+        let rel = rel.to_string_lossy().replace('\\', "/");
+        let object_key = key(&base, &rel);
+
+        let mut body = Vec::new();
+        File::open(entry.path())?.read_to_end(&mut body)?;
+        if client.etag(&object_key)? == Some(s3::md5_hex(&body)) {
+            skipped += 1;
+            continue;
+        }
+
+        let (content_type, content_encoding) = content_type(&rel);
+        info!("uploading {}", object_key);
+        client.put(&object_key, body, content_type, content_encoding)?;
+        uploaded += 1;
+    }
+    info!("uploaded {} files, {} unchanged", uploaded, skipped);
+
+    update_index(&client, &opts.prefix, ex_name)
+}
+
+/// The content type and encoding of a report file. Logs are stored
+/// gzipped, but served as text so they can be read in the browser.
+fn content_type(path: &str) -> (&'static str, Option<&'static str>) {
+    if path.ends_with(".txt.gz") {
+        return ("text/plain; charset=utf-8", Some("gzip"));
+    }
+    let content_type = match path.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "application/javascript",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("txt") | Some("md") | Some("lock") | Some("toml") => "text/plain; charset=utf-8",
+        Some("gz") => "application/gzip",
+        _ => "application/octet-stream",
+    };
+    (content_type, None)
+}
+
+/// Adds the experiment to `<prefix>/index.json`, and regenerates the index
+/// page from it.
+fn update_index(client: &S3Client, prefix: &str, ex_name: &str) -> Result<()> {
+    let index_key = key(prefix, "index.json");
+    let mut reports: Vec<PublishedReport> = match client.get(&index_key)? {
+        Some(json) => serde_json::from_slice(&json)?,
+        None => Vec::new(),
+    };
+
+    reports.retain(|r| r.name != ex_name);
+    reports.push(PublishedReport {
+                     name: ex_name.to_string(),
+                     published: UTC::now().to_rfc3339(),
+                 });
+    reports.sort_by(|a, b| b.published.cmp(&a.published));
+
+    let mut html = String::from("<!DOCTYPE html>\n<meta charset=\"utf-8\">\n\
+                                 <title>cargobomb reports</title>\n\
+                                 <h1>cargobomb reports</h1>\n<ul>\n");
+    for r in &reports {
+        html.push_str(&format!("  <li><a href=\"{0}/index.html\">{0}</a> ({1})</li>\n",
+                               escape_html(&r.name),
+                               escape_html(&r.published)));
+    }
+    html.push_str("</ul>\n");
+
+    info!("updating index {}", index_key);
+    client.put(&index_key,
+               serde_json::to_vec(&reports)?,
+               "application/json",
+               None)?;
+    client.put(&key(prefix, "index.html"),
+               html.into_bytes(),
+               "text/html; charset=utf-8",
+               None)
+}
+
+/// Escapes text for use in HTML, including in attribute values.
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escape_html("<script>alert('x & \"y\"')</script>"),
+                   "&lt;script&gt;alert(&#39;x &amp; &quot;y&quot;&#39;)&lt;/script&gt;");
+    }
+}
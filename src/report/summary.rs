@@ -0,0 +1,262 @@
+// Plain-text and markdown summaries of the report, for pasting into
+// GitHub comments and chat
+
+use super::{CrateResult, TestResults};
+use errors::*;
+use ex::Experiment;
+use file;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ReportFormat {
+    /// Only the HTML report
+    Html,
+    /// The HTML report and summary.md
+    Markdown,
+    /// The HTML report and summary.txt
+    Text,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ReportFormat> {
+        Ok(match s {
+               "html" => ReportFormat::Html,
+               "markdown" => ReportFormat::Markdown,
+               "text" => ReportFormat::Text,
+               s => bail!("invalid report format: {}", s),
+           })
+    }
+}
+
+impl ReportFormat {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "markdown",
+            ReportFormat::Text => "text",
+        }
+    }
+}
+
+/// The comparisons counted in the summary, in the order they're listed.
+/// Only the first two have their crates listed.
+static CATEGORIES: &'static [&'static str] = &["regressed",
+                                               "fixed",
+                                               "unknown",
+                                               "same-build-fail",
+                                               "same-test-fail",
+                                               "same-test-pass"];
+
+/// Writes the summary for `format` to `dest`, next to the HTML report.
+/// Links point into the report where it's published at `report_url`, or
+/// are relative to the report without it.
+pub fn write_summary(ex: &Experiment,
+                     res: &TestResults,
+                     dest: &Path,
+                     format: ReportFormat,
+                     report_url: Option<&str>)
+                     -> Result<()> {
+    let (file_name, summary) = match format {
+        ReportFormat::Html => return Ok(()),
+        ReportFormat::Markdown => ("summary.md", markdown_summary(ex, res, report_url)),
+        ReportFormat::Text => ("summary.txt", text_summary(ex, res, report_url)),
+    };
+
+    let path = dest.join(file_name);
+    info!("writing summary to {}", path.display());
+    file::write_string(&path, &summary)
+}
+
+/// A link to a file in the report, absolute if the report's URL is known.
+fn link(report_url: Option<&str>, path: &str) -> String {
+    match report_url {
+        Some(url) => format!("{}/{}", url.trim_right_matches('/'), path),
+        None => path.to_string(),
+    }
+}
+
+/// A link to the log of the second toolchain's run.
+fn log_link(c: &CrateResult, report_url: Option<&str>) -> Option<String> {
+    c.runs[1]
+        .as_ref()
+        .map(|r| link(report_url, &format!("{}/log.txt", r.log)))
+}
+
+/// The number of crates with each comparison, e.g. "regressed".
+pub fn comparison_counts(res: &TestResults) -> BTreeMap<String, usize> {
+    CATEGORIES
+        .iter()
+        .map(|label| (label.to_string(), crates_in(res, label).len()))
+        .collect()
+}
+
+fn crates_in<'a>(res: &'a TestResults, comparison: &str) -> Vec<&'a CrateResult> {
+    res.crates
+        .iter()
+        .filter(|c| c.res.to_str() == comparison)
+        .collect()
+}
+
+fn markdown_summary(ex: &Experiment, res: &TestResults, report_url: Option<&str>) -> String {
+    let mut s = String::new();
+    // Writing to a String can't fail
+    writeln!(s, "# Experiment {}\n", ex.name).expect("");
+    writeln!(s, "- toolchain 1: `{}`", ex.toolchains[0].to_string()).expect("");
+    writeln!(s, "- toolchain 2: `{}`", ex.toolchains[1].to_string()).expect("");
+    if let Some(url) = report_url {
+        writeln!(s, "- [full report]({})", link(Some(url), "index.html")).expect("");
+    }
+    s.push('\n');
+
+    writeln!(s, "| result | crates |").expect("");
+    writeln!(s, "| --- | ---: |").expect("");
+    for label in CATEGORIES {
+        writeln!(s, "| {} | {} |", label, crates_in(res, label).len()).expect("");
+    }
+
+    for label in &CATEGORIES[..2] {
+        let crates = crates_in(res, label);
+        if crates.is_empty() {
+            continue;
+        }
+        writeln!(s, "\n## {} ({})\n", label, crates.len()).expect("");
+        for c in crates {
+            match log_link(c, report_url) {
+                Some(log) => writeln!(s, "- [{}]({})", c.name, log).expect(""),
+                None => writeln!(s, "- {}", c.name).expect(""),
+            }
+        }
+    }
+
+    s
+}
+
+fn text_summary(ex: &Experiment, res: &TestResults, report_url: Option<&str>) -> String {
+    let mut s = String::new();
+    writeln!(s, "experiment: {}", ex.name).expect("");
+    writeln!(s, "toolchain 1: {}", ex.toolchains[0].to_string()).expect("");
+    writeln!(s, "toolchain 2: {}", ex.toolchains[1].to_string()).expect("");
+    if let Some(url) = report_url {
+        writeln!(s, "report: {}", link(Some(url), "index.html")).expect("");
+    }
+    s.push('\n');
+
+    for label in CATEGORIES {
+        writeln!(s, "{}: {}", label, crates_in(res, label).len()).expect("");
+    }
+
+    for label in &CATEGORIES[..2] {
+        let crates = crates_in(res, label);
+        if crates.is_empty() {
+            continue;
+        }
+        writeln!(s, "\n{} crates:", label).expect("");
+        for c in crates {
+            match log_link(c, report_url) {
+                Some(log) => writeln!(s, "  {} ({})", c.name, log).expect(""),
+                None => writeln!(s, "  {}", c.name).expect(""),
+            }
+        }
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ex::ExMode;
+    use report::Comparison;
+    use report::tests::{build_result, crate_result};
+    use std::fs;
+    use tempdir::TempDir;
+    use toolchain::Toolchain;
+
+    fn experiment() -> Experiment {
+        Experiment {
+            name: "pr-1234".to_string(),
+            crates: Vec::new(),
+            toolchains: vec![Toolchain::Dist("stable".to_string()),
+                             Toolchain::Dist("beta".to_string())],
+            mode: ExMode::BuildAndTest,
+            targets: Vec::new(),
+        }
+    }
+
+    fn with_log(mut c: CrateResult, log: &str) -> CrateResult {
+        let mut run = build_result();
+        run.log = log.to_string();
+        c.runs[1] = Some(run);
+        c
+    }
+
+    fn results() -> TestResults {
+        TestResults {
+            crates: vec![with_log(crate_result("foo-0.1.0", Comparison::Regressed),
+                                  "beta/reg/foo"),
+                         crate_result("bar-0.2.0", Comparison::Fixed),
+                         with_log(crate_result("baz-1.0.0", Comparison::SameTestPass),
+                                  "beta/reg/baz"),
+                         crate_result("qux-0.3.0", Comparison::SameTestPass)],
+            slowdowns: Vec::new(),
+            bench_regressions: Vec::new(),
+            clusters: Vec::new(),
+            ices: Vec::new(),
+            toolchain_versions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn counts_comparisons() {
+        let counts = comparison_counts(&results());
+        assert_eq!(counts.len(), CATEGORIES.len());
+        assert_eq!(counts["regressed"], 1);
+        assert_eq!(counts["fixed"], 1);
+        assert_eq!(counts["unknown"], 0);
+        assert_eq!(counts["same-test-pass"], 2);
+    }
+
+    #[test]
+    fn writes_markdown_summaries() {
+        let dir = TempDir::new("cargobomb-summary").unwrap();
+        write_summary(&experiment(),
+                      &results(),
+                      dir.path(),
+                      ReportFormat::Markdown,
+                      Some("https://example.com/r/"))
+                .unwrap();
+        let summary = file::read_string(&dir.path().join("summary.md")).unwrap();
+        assert!(summary.starts_with("# Experiment pr-1234\n\n- toolchain 1: `stable`\n\
+                                     - toolchain 2: `beta`\n\
+                                     - [full report](https://example.com/r/index.html)\n"));
+        assert!(summary.contains("| regressed | 1 |\n| fixed | 1 |\n| unknown | 0 |\n"));
+        assert!(summary.contains("| same-test-pass | 2 |\n"));
+        assert!(summary.contains("## regressed (1)\n\n\
+                                  - [foo-0.1.0](https://example.com/r/beta/reg/foo/log.txt)\n"));
+        assert!(summary.contains("## fixed (1)\n\n- bar-0.2.0\n"));
+        // Only regressions and fixes are listed
+        assert!(!summary.contains("baz-1.0.0"));
+    }
+
+    #[test]
+    fn writes_text_summaries() {
+        let dir = TempDir::new("cargobomb-summary").unwrap();
+        write_summary(&experiment(), &results(), dir.path(), ReportFormat::Text, None).unwrap();
+        let summary = file::read_string(&dir.path().join("summary.txt")).unwrap();
+        assert!(summary.starts_with("experiment: pr-1234\ntoolchain 1: stable\n\
+                                     toolchain 2: beta\n\nregressed: 1\n"));
+        assert!(summary.contains("regressed crates:\n  foo-0.1.0 (beta/reg/foo/log.txt)\n"));
+        assert!(summary.contains("fixed crates:\n  bar-0.2.0\n"));
+        assert!(!summary.contains("baz-1.0.0"));
+
+        // The HTML report has no summary
+        let dir = TempDir::new("cargobomb-summary").unwrap();
+        write_summary(&experiment(), &results(), dir.path(), ReportFormat::Html, None).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -1,14 +1,25 @@
+use diagnostics::Diagnostic;
+use docker::ResourceUsage;
 use errors::*;
 use ex::ExCrate;
 use ex::Experiment;
 use ex::ex_dir;
 use file;
+use flate2::Compression;
+use flate2::read::{GzDecoder, GzEncoder};
+use flate2::write;
 use gh_mirrors;
+use ice::Ice;
+use libtest::{BenchResult, TestCase};
 use log;
+use serde_json;
+use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 use std::fs;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::str::FromStr;
+use tar::{Archive, Builder};
 use toolchain::Toolchain;
 use util;
 
@@ -26,8 +37,23 @@ pub trait CrateResultWriter {
 
     fn record_results<F>(&self, f: F) -> Result<TestResult> where F: FnOnce() -> Result<TestResult>;
     fn load_test_result(&self) -> Result<Option<TestResult>>;
-    fn read_log(&self) -> Result<fs::File>;
+    /// Record the data collected while testing, besides the result itself.
+    fn record_output(&self, output: &TestOutput) -> Result<()>;
+    fn load_target_results(&self) -> Result<BTreeMap<String, TestResult>>;
+    fn load_usage(&self) -> Result<Option<CrateUsage>>;
+    fn load_benches(&self) -> Result<Vec<BenchResult>>;
+    fn load_tests(&self) -> Result<Vec<TestCase>>;
+    fn load_diagnostics(&self) -> Result<Vec<Diagnostic>>;
+    fn load_ice(&self) -> Result<Option<Ice>>;
+    fn read_log(&self) -> Result<Box<Read + Send>>;
+    /// The log, gzipped.
+    fn read_compressed_log(&self) -> Result<Box<Read>>;
     fn delete_result(&self) -> Result<()>;
+    /// The result and everything recorded with it, as a gzipped tarball,
+    /// for moving results between machines.
+    fn read_archive(&self) -> Result<Vec<u8>>;
+    /// Replaces the result with one packed by `read_archive`.
+    fn write_archive(&self, archive: &[u8]) -> Result<()>;
 }
 
 fn crate_to_dir(c: &ExCrate) -> String {
@@ -98,8 +124,31 @@ impl<'a> CrateResultWriter for ResultWriter<'a> {
         PathBuf::from(tc).join(crate_to_dir(&self.crate_))
     }
 
-    fn read_log(&self) -> Result<fs::File> {
-        fs::File::open(self.result_log()).chain_err(|| "Couldn't open result file.")
+    fn read_log(&self) -> Result<Box<Read + Send>> {
+        let compressed_log = self.result_log_gz();
+        if compressed_log.exists() {
+            let f = fs::File::open(compressed_log)
+                .chain_err(|| "Couldn't open result file.")?;
+            Ok(Box::new(GzDecoder::new(f)?))
+        } else {
+            // Results recorded before logs were compressed
+            let f = fs::File::open(self.result_log())
+                .chain_err(|| "Couldn't open result file.")?;
+            Ok(Box::new(f))
+        }
+    }
+
+    fn read_compressed_log(&self) -> Result<Box<Read>> {
+        let compressed_log = self.result_log_gz();
+        if compressed_log.exists() {
+            let f = fs::File::open(compressed_log)
+                .chain_err(|| "Couldn't open result file.")?;
+            Ok(Box::new(f))
+        } else {
+            let f = fs::File::open(self.result_log())
+                .chain_err(|| "Couldn't open result file.")?;
+            Ok(Box::new(GzEncoder::new(f, Compression::Default)))
+        }
     }
 
     fn record_results<F>(&self, f: F) -> Result<TestResult>
@@ -109,7 +158,9 @@ impl<'a> CrateResultWriter for ResultWriter<'a> {
         let log_file = self.result_log();
         let result_file = self.result_file();
 
-        let result = log::redirect(&log_file, f)?;
+        let result = log::redirect(&log_file, f);
+        self.compress_log()?;
+        let result = result?;
         file::write_string(&result_file, &result.to_string())?;
 
         Ok(result)
@@ -126,6 +177,120 @@ impl<'a> CrateResultWriter for ResultWriter<'a> {
             Ok(None)
         }
     }
+
+    fn record_output(&self, output: &TestOutput) -> Result<()> {
+        let json = serde_json::to_string(&output.usage)?;
+        file::write_string(&self.usage_file(), &json)?;
+
+        if !output.targets.is_empty() {
+            let json = serde_json::to_string(&output.targets)?;
+            file::write_string(&self.target_results_file(), &json)?;
+        }
+
+        if !output.benches.is_empty() {
+            let json = serde_json::to_string(&output.benches)?;
+            file::write_string(&self.benches_file(), &json)?;
+        }
+
+        if !output.tests.is_empty() {
+            let json = serde_json::to_string(&output.tests)?;
+            file::write_string(&self.tests_file(), &json)?;
+        }
+
+        if !output.diagnostics.is_empty() {
+            let json = serde_json::to_string(&output.diagnostics)?;
+            file::write_string(&self.diagnostics_file(), &json)?;
+        }
+
+        if let Some(ref ice) = output.ice {
+            let json = serde_json::to_string(ice)?;
+            file::write_string(&self.ice_file(), &json)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_target_results(&self) -> Result<BTreeMap<String, TestResult>> {
+        let target_results_file = self.target_results_file();
+        if target_results_file.exists() {
+            let s = file::read_string(&target_results_file)?;
+            Ok(serde_json::from_str(&s)?)
+        } else {
+            Ok(BTreeMap::new())
+        }
+    }
+
+    fn load_usage(&self) -> Result<Option<CrateUsage>> {
+        let usage_file = self.usage_file();
+        if usage_file.exists() {
+            let s = file::read_string(&usage_file)?;
+            Ok(Some(serde_json::from_str(&s)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn load_benches(&self) -> Result<Vec<BenchResult>> {
+        let benches_file = self.benches_file();
+        if benches_file.exists() {
+            let s = file::read_string(&benches_file)?;
+            Ok(serde_json::from_str(&s)?)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn load_tests(&self) -> Result<Vec<TestCase>> {
+        let tests_file = self.tests_file();
+        if tests_file.exists() {
+            let s = file::read_string(&tests_file)?;
+            Ok(serde_json::from_str(&s)?)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn load_diagnostics(&self) -> Result<Vec<Diagnostic>> {
+        let diagnostics_file = self.diagnostics_file();
+        if diagnostics_file.exists() {
+            let s = file::read_string(&diagnostics_file)?;
+            Ok(serde_json::from_str(&s)?)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn load_ice(&self) -> Result<Option<Ice>> {
+        let ice_file = self.ice_file();
+        if ice_file.exists() {
+            let s = file::read_string(&ice_file)?;
+            Ok(Some(serde_json::from_str(&s)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_archive(&self) -> Result<Vec<u8>> {
+        let mut builder = Builder::new(write::GzEncoder::new(Vec::new(), Compression::Default));
+        for entry in fs::read_dir(self.result_dir())? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                builder.append_file(entry.file_name(), &mut fs::File::open(entry.path())?)?;
+            }
+        }
+        Ok(builder.into_inner()?.finish()?)
+    }
+
+    fn write_archive(&self, archive: &[u8]) -> Result<()> {
+        self.init()?;
+        Archive::new(GzDecoder::new(archive)?)
+            .unpack(self.result_dir())?;
+        if self.load_test_result()?.is_none() {
+            self.delete_result()?;
+            bail!("result archive for {} has no result", self.crate_);
+        }
+        Ok(())
+    }
 }
 
 impl<'a> ResultWriter<'a> {
@@ -149,6 +314,76 @@ impl<'a> ResultWriter<'a> {
     fn result_log(&self) -> PathBuf {
         self.result_dir().join("log.txt")
     }
+
+    fn result_log_gz(&self) -> PathBuf {
+        self.result_dir().join("log.txt.gz")
+    }
+
+    /// Replaces the log with a gzipped copy, as some crates produce
+    /// multi-megabyte logs.
+    fn compress_log(&self) -> Result<()> {
+        let log = self.result_log();
+        if !log.exists() {
+            return Ok(());
+        }
+
+        let out = fs::File::create(self.result_log_gz())?;
+        let mut encoder = write::GzEncoder::new(out, Compression::Default);
+        io::copy(&mut fs::File::open(&log)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(&log)?;
+
+        Ok(())
+    }
+
+    fn target_results_file(&self) -> PathBuf {
+        self.result_dir().join("targets.json")
+    }
+
+    fn usage_file(&self) -> PathBuf {
+        self.result_dir().join("usage.json")
+    }
+
+    fn benches_file(&self) -> PathBuf {
+        self.result_dir().join("benches.json")
+    }
+
+    fn tests_file(&self) -> PathBuf {
+        self.result_dir().join("tests.json")
+    }
+
+    fn diagnostics_file(&self) -> PathBuf {
+        self.result_dir().join("diagnostics.json")
+    }
+
+    fn ice_file(&self) -> PathBuf {
+        self.result_dir().join("ice.json")
+    }
+}
+
+/// Data collected while testing a crate, recorded alongside its result.
+#[derive(Default)]
+pub struct TestOutput {
+    pub usage: CrateUsage,
+    /// Results of building for each cross-compilation target
+    pub targets: BTreeMap<String, TestResult>,
+    pub benches: Vec<BenchResult>,
+    /// Outcomes of the individual tests of `cargo test`
+    pub tests: Vec<TestCase>,
+    /// Compiler errors from the build
+    pub diagnostics: Vec<Diagnostic>,
+    /// The internal compiler error that ended testing, if any
+    pub ice: Option<Ice>,
+}
+
+/// Resources consumed while building and testing a crate.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct CrateUsage {
+    pub build: Option<ResourceUsage>,
+    pub test: Option<ResourceUsage>,
+    /// One sample per clean build in compile-time experiments
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub compile_samples: Vec<ResourceUsage>,
 }
 
 
@@ -158,6 +393,8 @@ pub enum TestResult {
     BuildFail,
     TestFail,
     TestPass,
+    /// The compiler crashed
+    Ice,
 }
 impl Display for TestResult {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -173,6 +410,7 @@ impl FromStr for TestResult {
             "build-fail" => Ok(TestResult::BuildFail),
             "test-fail" => Ok(TestResult::TestFail),
             "test-pass" => Ok(TestResult::TestPass),
+            "ice" => Ok(TestResult::Ice),
             _ => Err(format!("bogus test result: {}", s).into()),
         }
     }
@@ -184,6 +422,7 @@ impl TestResult {
                 TestResult::BuildFail => "build-fail",
                 TestResult::TestFail => "test-fail",
                 TestResult::TestPass => "test-pass",
+                TestResult::Ice => "ice",
             }
             .to_string()
     }
@@ -1,8 +1,10 @@
 #![deny(unused_must_use)]
 
+use diagnostics;
 use errors::*;
 use futures::{Future, Stream};
 use futures::stream::MergedItem;
+use slog::Logger;
 use slog_scope;
 use std::convert::AsRef;
 use std::ffi::OsStr;
@@ -78,24 +80,41 @@ pub fn run_capture<S>(cd: Option<&Path>,
     }
 }
 
-struct ProcessOutput {
-    status: ExitStatus,
-    stdout: Vec<String>,
-    stderr: Vec<String>,
+/// Runs a command, capturing its output whether or not it succeeds. Only
+/// failing to run the command, or killing it after a timeout, is an error.
+/// The command is expected to be cargo, possibly with
+/// `--message-format=json`, whose messages are logged as rustc would have
+/// printed them; the captured output keeps the JSON.
+pub fn run_output(name: &str, args: &[&str], env: &[(&str, &str)]) -> Result<ProcessOutput> {
+    let mut cmd = Command::new(name);
+
+    cmd.args(args);
+    for &(k, v) in env {
+        cmd.env(k, v);
+    }
+
+    info!("running `{:?}`", cmd);
+    log_command_(cmd, true, true)
+}
+
+pub struct ProcessOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
 }
 
 fn log_command(cmd: Command) -> Result<ProcessOutput> {
-    log_command_(cmd, false)
+    log_command_(cmd, false, false)
 }
 
 fn log_command_capture(cmd: Command) -> Result<ProcessOutput> {
-    log_command_(cmd, true)
+    log_command_(cmd, true, false)
 }
 
 const MAX_TIMEOUT_SECS: u64 = 60 * 10 * 2;
 const HEARTBEAT_TIMEOUT_SECS: u64 = 60 * 2;
 
-fn log_command_(mut cmd: Command, capture: bool) -> Result<ProcessOutput> {
+fn log_command_(mut cmd: Command, capture: bool, render_json: bool) -> Result<ProcessOutput> {
     let mut core = Core::new().unwrap();
     let timer = tokio_timer::wheel()
         .max_timeout(Duration::from_secs(MAX_TIMEOUT_SECS * 2))
@@ -116,7 +135,7 @@ fn log_command_(mut cmd: Command, capture: bool) -> Result<ProcessOutput> {
     let stdout = lines(BufReader::new(stdout)).map({
                                                        let logger = logger.clone();
                                                        move |line| {
-                                                           slog_info!(logger, "blam! {}", line);
+                                                           log_stdout(&logger, &line, render_json);
                                                            line
                                                        }
                                                    });
@@ -184,6 +203,19 @@ fn log_command_(mut cmd: Command, capture: bool) -> Result<ProcessOutput> {
        })
 }
 
+/// Logs a line of a command's stdout, or the compiler messages in it when
+/// it's a cargo JSON message and `render_json` is set.
+fn log_stdout(logger: &Logger, line: &str, render_json: bool) {
+    match diagnostics::rendered_lines(line) {
+        Some(rendered) if render_json => {
+            for l in rendered {
+                slog_info!(logger, "blam! {}", l);
+            }
+        }
+        _ => slog_info!(logger, "blam! {}", line),
+    }
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(type_complexity))]
 fn unmerge<T1, T2, S>(reader: S) -> Box<Future<Item = (Vec<T1>, Vec<T2>), Error = S::Error>>
     where S: Stream<Item = MergedItem<T1, T2>> + 'static,
@@ -0,0 +1,340 @@
+// The work handed out to agents: each crate and toolchain of the running
+// experiments that has no result yet, leased to one agent at a time.
+//
+// The tests still to hand out are listed from the results on disk once
+// per experiment, then kept up to date as leases are given out and end.
+// They, the leases and the tests agents couldn't run are kept in memory
+// only. Restarting the server forgets them, so uploads for earlier leases
+// are refused and the tests are handed out again.
+
+use chrono::{DateTime, Duration, UTC};
+use errors::*;
+use ex::{self, ExCrate, Experiment};
+use progress::{CurrentTest, Progress};
+use rand::{OsRng, Rng};
+use results::{CrateResultWriter, ExperimentResultDB, FileDB};
+use std::collections::{BTreeMap, VecDeque};
+use toolchain::Toolchain;
+use util;
+
+// How long a claim lasts unless renewed. Agents renew their claims while
+// testing, so the work of a dead agent is requeued within this time.
+pub const LEASE_SECS: i64 = 10 * 60;
+
+#[derive(Clone)]
+pub struct Lease {
+    /// Random, so that only the agent given the lease can end it
+    pub id: String,
+    pub agent: String,
+    pub ex_name: String,
+    pub krate: ExCrate,
+    pub toolchain: Toolchain,
+    pub started: DateTime<UTC>,
+    pub expires: DateTime<UTC>,
+}
+
+/// A test an agent couldn't run, which isn't handed out again.
+struct Failure {
+    ex_name: String,
+    krate: ExCrate,
+    toolchain: Toolchain,
+}
+
+#[derive(Default)]
+pub struct Agents {
+    leases: Vec<Lease>,
+    failures: Vec<Failure>,
+    /// The tests of each running experiment that have neither a result, a
+    /// failure nor a live lease, in the order they're handed out
+    pending: BTreeMap<String, VecDeque<(ExCrate, Toolchain)>>,
+}
+
+impl Agents {
+    /// Leases the next pending crate and toolchain of the experiments in
+    /// `running` to `agent`, if there is one.
+    pub fn claim(&mut self, running: &[String], agent: &str) -> Result<Option<Lease>> {
+        self.expire();
+        let id = lease_id()?;
+
+        let stopped = self.pending
+            .keys()
+            .filter(|ex_name| !running.contains(ex_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for ex_name in stopped {
+            self.pending.remove(&ex_name);
+        }
+
+        for ex_name in running {
+            if !self.pending.contains_key(ex_name) {
+                match self.unfinished(ex_name) {
+                    Ok(tests) => {
+                        self.pending.insert(ex_name.clone(), tests);
+                    }
+                    Err(e) => {
+                        error!("unable to list the tests of running experiment {}", ex_name);
+                        util::report_error(&e);
+                        continue;
+                    }
+                }
+            }
+
+            let next = self.pending.get_mut(ex_name).and_then(|p| p.pop_front());
+            if let Some((krate, tc)) = next {
+                info!("leasing {} against {} for {} to {}",
+                      krate,
+                      tc.to_string(),
+                      ex_name,
+                      agent);
+                let lease = Lease {
+                    id: id,
+                    agent: agent.to_string(),
+                    ex_name: ex_name.clone(),
+                    krate: krate,
+                    toolchain: tc,
+                    started: UTC::now(),
+                    expires: UTC::now() + Duration::seconds(LEASE_SECS),
+                };
+                self.leases.push(lease.clone());
+                return Ok(Some(lease));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Extends a lease, failing if it has expired and been requeued.
+    pub fn renew(&mut self, id: &str, agent: &str) -> Result<()> {
+        self.expire();
+        match self.leases
+                  .iter_mut()
+                  .find(|l| l.id == id && l.agent == agent) {
+            Some(lease) => {
+                lease.expires = UTC::now() + Duration::seconds(LEASE_SECS);
+                Ok(())
+            }
+            None => bail!(ErrorKind::NotFound(format!("lease {} held by {}", id, agent))),
+        }
+    }
+
+    /// Records the results of a lease held by `agent`, packed with
+    /// `CrateResultWriter::read_archive`, and ends the lease.
+    pub fn complete(&mut self, id: &str, agent: &str, archive: &[u8]) -> Result<()> {
+        let lease = self.take(id, agent)?;
+        if let Err(e) = write_results(&lease, archive) {
+            self.requeue(lease);
+            return Err(e);
+        }
+        info!("{} completed {} against {} for {}",
+              lease.agent,
+              lease.krate,
+              lease.toolchain.to_string(),
+              lease.ex_name);
+        Ok(())
+    }
+
+    /// Ends a lease held by `agent` whose test couldn't be run, recording
+    /// the failure so that it counts as an error and isn't retried.
+    pub fn fail(&mut self, id: &str, agent: &str, error: &str) -> Result<()> {
+        let lease = self.take(id, agent)?;
+        error!("{} couldn't test {} against {} for {}: {}",
+               lease.agent,
+               lease.krate,
+               lease.toolchain.to_string(),
+               lease.ex_name,
+               error);
+        self.failures
+            .push(Failure {
+                      ex_name: lease.ex_name,
+                      krate: lease.krate,
+                      toolchain: lease.toolchain,
+                  });
+        Ok(())
+    }
+
+    /// Counts the tests of an experiment that agents couldn't run as
+    /// errors.
+    pub fn record_failures(&self, ex_name: &str, progress: &mut Progress) {
+        for f in self.failures.iter().filter(|f| f.ex_name == ex_name) {
+            progress.record(&f.toolchain, None);
+        }
+    }
+
+    /// The tests agents are running for an experiment.
+    pub fn current(&self, ex_name: &str) -> Vec<CurrentTest> {
+        self.leases
+            .iter()
+            .filter(|l| l.ex_name == ex_name)
+            .map(|l| {
+                     CurrentTest {
+                         krate: l.krate.to_string(),
+                         toolchain: l.toolchain.to_string(),
+                         agent: Some(l.agent.clone()),
+                         started: l.started.to_rfc3339(),
+                     }
+                 })
+            .collect()
+    }
+
+    /// Removes a lease to end it, failing if `agent` doesn't hold it.
+    fn take(&mut self, id: &str, agent: &str) -> Result<Lease> {
+        let i = match self.leases.iter().position(|l| l.id == id) {
+            Some(i) => i,
+            None => bail!(ErrorKind::NotFound(format!("lease {}", id))),
+        };
+        if self.leases[i].agent != agent {
+            bail!(ErrorKind::Forbidden(format!("lease {} is not held by {}", id, agent)));
+        }
+        Ok(self.leases.remove(i))
+    }
+
+    /// Requeues the work of expired leases.
+    fn expire(&mut self) {
+        let now = UTC::now();
+        let (expired, live): (Vec<_>, Vec<_>) =
+            self.leases.drain(..).partition(|l| l.expires <= now);
+        self.leases = live;
+        for lease in expired {
+            info!("lease of {} against {} for {} by {} expired, requeueing",
+                  lease.krate,
+                  lease.toolchain.to_string(),
+                  lease.ex_name,
+                  lease.agent);
+            self.requeue(lease);
+        }
+    }
+
+    /// Hands out the test of an ended lease again, next. If its experiment
+    /// isn't listed, it's found when the experiment is next listed.
+    fn requeue(&mut self, lease: Lease) {
+        if let Some(pending) = self.pending.get_mut(&lease.ex_name) {
+            pending.push_front((lease.krate, lease.toolchain));
+        }
+    }
+
+    /// The tests of an experiment that have neither a result, a failure nor
+    /// a live lease.
+    fn unfinished(&self, ex_name: &str) -> Result<VecDeque<(ExCrate, Toolchain)>> {
+        let ex = Experiment::load(ex_name)?;
+        let db = FileDB::for_experiment(&ex);
+
+        let mut tests = VecDeque::new();
+        for (krate, _) in ex::ex_crates_and_dirs(&ex)? {
+            for tc in &ex.toolchains {
+                let leased = self.leases
+                    .iter()
+                    .any(|l| l.ex_name == ex_name && l.krate == krate && l.toolchain == *tc);
+                let failed = self.failures
+                    .iter()
+                    .any(|f| f.ex_name == ex_name && f.krate == krate && f.toolchain == *tc);
+                if leased || failed || db.for_crate(&krate, tc).load_test_result()?.is_some() {
+                    continue;
+                }
+                tests.push_back((krate.clone(), tc.clone()));
+            }
+        }
+
+        Ok(tests)
+    }
+}
+
+fn write_results(lease: &Lease, archive: &[u8]) -> Result<()> {
+    let ex = Experiment::load(&lease.ex_name)?;
+    let writer = FileDB::for_experiment(&ex).for_crate(&lease.krate, &lease.toolchain);
+    writer.write_archive(archive)
+}
+
+/// 128 random bits in hex.
+fn lease_id() -> Result<String> {
+    let mut rng = OsRng::new()?;
+    Ok(format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn krate() -> ExCrate {
+        ExCrate::Version {
+            name: "foo".to_string(),
+            version: "0.1.0".to_string(),
+        }
+    }
+
+    fn lease(id: &str, agent: &str) -> Lease {
+        Lease {
+            id: id.to_string(),
+            agent: agent.to_string(),
+            ex_name: "ex".to_string(),
+            krate: krate(),
+            toolchain: Toolchain::Dist("stable".into()),
+            started: UTC::now(),
+            expires: UTC::now() + Duration::seconds(LEASE_SECS),
+        }
+    }
+
+    #[test]
+    fn only_the_holder_ends_a_lease() {
+        let mut agents = Agents::default();
+        agents.leases.push(lease("1", "alice"));
+
+        assert!(agents.fail("2", "alice", "oops").is_err());
+        match agents.fail("1", "bob", "oops") {
+            Err(Error(ErrorKind::Forbidden(_), _)) => (),
+            _ => panic!("another agent ended the lease"),
+        }
+        assert_eq!(agents.leases.len(), 1);
+
+        agents.fail("1", "alice", "oops").unwrap();
+        assert!(agents.leases.is_empty());
+        let mut progress = Progress::new(&[Toolchain::Dist("stable".into())], 1);
+        agents.record_failures("ex", &mut progress);
+        agents.record_failures("other", &mut progress);
+        assert_eq!(progress.results.get("error"), Some(&1));
+        assert_eq!(progress.done(), 1);
+    }
+
+    fn pending(toolchains: &[&str]) -> VecDeque<(ExCrate, Toolchain)> {
+        toolchains
+            .iter()
+            .map(|tc| (krate(), Toolchain::Dist(tc.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn hands_out_pending_tests_once() {
+        let mut agents = Agents::default();
+        agents
+            .pending
+            .insert("ex".to_string(), pending(&["stable", "beta"]));
+        let running = vec!["ex".to_string()];
+
+        let first = agents.claim(&running, "alice").unwrap().unwrap();
+        assert_eq!(first.toolchain, Toolchain::Dist("stable".into()));
+        let second = agents.claim(&running, "bob").unwrap().unwrap();
+        assert_eq!(second.toolchain, Toolchain::Dist("beta".into()));
+        assert!(agents.claim(&running, "carol").unwrap().is_none());
+
+        // A test that couldn't be run isn't handed out again, an expired
+        // one is
+        agents.fail(&first.id, "alice", "oops").unwrap();
+        agents.leases[0].expires = UTC::now() - Duration::seconds(1);
+        let again = agents.claim(&running, "carol").unwrap().unwrap();
+        assert_eq!(again.toolchain, second.toolchain);
+        assert_eq!(again.agent, "carol");
+        assert!(again.id != second.id);
+        assert!(agents.claim(&running, "dave").unwrap().is_none());
+        assert!(agents.renew(&second.id, "bob").is_err());
+
+        // Experiments that stop running are forgotten
+        assert!(agents.claim(&[], "dave").unwrap().is_none());
+        assert!(agents.pending.is_empty());
+    }
+
+    #[test]
+    fn lease_ids_are_random() {
+        let id = lease_id().unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id != lease_id().unwrap());
+    }
+}
@@ -4,59 +4,506 @@
 //!
 //! The responses are calculated in the server.rs file.
 
-pub mod get {
+pub mod error {
+    /// The body of every error response.
+    #[derive(Serialize, Deserialize)]
+    pub struct Response {
+        /// What went wrong, with its causes
+        pub error: String,
+    }
+}
+
+pub mod ex_list {
+    use errors::*;
+    use ex;
     use server::{Data, Params};
+    use server::queue::ExStatus;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Experiment {
+        pub name: String,
+        pub toolchains: Vec<String>,
+        pub crates: usize,
+        /// The experiment's status in the queue, unless it was defined
+        /// outside the server
+        pub status: Option<ExStatus>,
+    }
+
+    /// Every defined experiment, by name.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(data: &Data, _params: Params) -> Result<Vec<Experiment>> {
+        let queued = data.queue.lock().unwrap().list();
+        let mut experiments = Vec::new();
+        for name in ex::list()? {
+            let ex = ex::Experiment::load(&name)?;
+            let status = queued.iter().find(|q| q.name == name).map(|q| q.status);
+            experiments.push(Experiment {
+                                 name: name,
+                                 toolchains: ex.toolchains
+                                     .iter()
+                                     .map(|tc| tc.to_string())
+                                     .collect(),
+                                 crates: ex.crates.len(),
+                                 status: status,
+                             });
+        }
+        Ok(experiments)
+    }
+}
+
+pub mod ex_report {
+    use errors::*;
+    use ex;
+    use report::{TestResults, generate_report};
+    use server::{Data, Params};
+
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<TestResults> {
+        let ex_name = params.find("experiment").unwrap();
+        let ex = ex::Experiment::load(ex_name)?;
+        generate_report(&ex, None)
+    }
+}
+
+pub mod ex_config {
+    use errors::*;
+    use ex;
+    use server::{Data, Params};
+
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<ex::Experiment> {
+        let ex_name = params.find("experiment").unwrap();
+        ex::Experiment::load(ex_name)
+    }
+}
+
+pub mod ex_progress {
+    use errors::*;
+    use progress::Progress;
+    use server::{Data, Params};
+
+    /// The progress of the experiment's run, or null if it hasn't started.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<Option<Progress>> {
+        let ex_name = params.find("experiment").unwrap();
+        Progress::load(ex_name)
+    }
+}
+
+pub mod ex_crate_result {
+    use errors::*;
+    use ex::Experiment;
+    use report::{self, BuildTestResult};
+    use server::{Data, Params};
+
+    /// The result of testing a crate, named as in the experiment's report,
+    /// against one of its toolchains.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<BuildTestResult> {
+        let ex = Experiment::load(params.find("experiment").unwrap())?;
+        let krate = report::find_crate(&ex, params.find("crate").unwrap())?;
+        let tc = report::find_toolchain(&ex, params.find("toolchain").unwrap())?;
+        report::crate_result(&ex, &krate, &tc)
+    }
+}
+
+pub mod ex_crate_log {
+    use errors::*;
+    use ex::Experiment;
+    use report;
+    use results::{CrateResultWriter, ExperimentResultDB, FileDB};
+    use server::{Data, Params};
+    use std::io::Read;
+
+    /// The full log of testing a crate against one of the experiment's
+    /// toolchains.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<Box<Read + Send>> {
+        let ex = Experiment::load(params.find("experiment").unwrap())?;
+        let krate = report::find_crate(&ex, params.find("crate").unwrap())?;
+        let tc = report::find_toolchain(&ex, params.find("toolchain").unwrap())?;
+        FileDB::for_experiment(&ex)
+            .for_crate(&krate, &tc)
+            .read_log()
+    }
+}
+
+pub mod ex_shared {
+    use errors::*;
+    use ex;
+    use server::{Data, Params};
+
+    /// The experiment's shared data, packed by `ex::pack_shared_data`.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_data: &Data, params: Params) -> Result<Vec<u8>> {
+        let ex = ex::Experiment::load(params.find("experiment").unwrap())?;
+        ex::pack_shared_data(&ex.name)
+    }
+}
+
+pub mod agent_claim {
+    use errors::*;
+    use ex::ExCrate;
+    use server::{Data, Params};
+    use server::agents::LEASE_SECS;
+    use server::queue::ExStatus;
+    use toolchain::Toolchain;
+    use util;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {
+        pub agent: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Work {
+        pub lease: String,
+        pub experiment: String,
+        pub krate: ExCrate,
+        pub toolchain: Toolchain,
+        /// How long the lease lasts unless renewed
+        pub lease_secs: i64,
+    }
 
     #[derive(Serialize, Deserialize)]
     pub struct Response {
-        pub text: String,
+        pub work: Option<Work>,
     }
 
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-    pub fn handler(_data: &Data, _params: Params) -> Response {
-        Response { text: String::from("This is a response!") }
+    pub fn handler(req: Request, data: &Data, _params: Params) -> Result<Response> {
+        // The name ends up in URLs
+        if !util::is_safe_name(&req.agent) {
+            bail!(ErrorKind::BadRequest(format!("invalid agent name {}", req.agent)));
+        }
+        let running = data.queue.lock().unwrap().with_status(ExStatus::Running);
+        let lease = data.agents.lock().unwrap().claim(&running, &req.agent)?;
+        let work = lease.map(|l| {
+                                 Work {
+                                     lease: l.id,
+                                     experiment: l.ex_name,
+                                     krate: l.krate,
+                                     toolchain: l.toolchain,
+                                     lease_secs: LEASE_SECS,
+                                 }
+                             });
+        Ok(Response { work })
     }
 }
 
-pub mod post {
+pub mod agent_renew {
+    use errors::*;
     use server::{Data, Params};
 
     #[derive(Serialize, Deserialize)]
     pub struct Request {
-        pub input: String,
+        pub agent: String,
     }
 
     #[derive(Serialize, Deserialize)]
     pub struct Response {
-        pub out: String,
+        /// False if the lease expired and the work was requeued
+        pub renewed: bool,
     }
 
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-    pub fn handler(post: Request, _data: &Data, _params: Params) -> Response {
-        Response { out: format!("Got {}!", post.input) }
+    pub fn handler(req: Request, data: &Data, params: Params) -> Result<Response> {
+        let lease = params.find("lease").unwrap();
+        let mut agents = data.agents.lock().unwrap();
+        Ok(Response { renewed: agents.renew(lease, &req.agent).is_ok() })
     }
 }
 
-pub mod ex_report {
-    use ex;
-    use report::{TestResults, generate_report};
+pub mod agent_complete {
+    use errors::*;
+    use server::{Data, Params};
+
+    /// Takes the results of a lease, packed by
+    /// `CrateResultWriter::read_archive`, from the agent holding it.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(archive: Vec<u8>, data: &Data, params: Params) -> Result<()> {
+        let lease = params.find("lease").unwrap();
+        let agent = params.find("agent").unwrap();
+        let mut agents = data.agents.lock().unwrap();
+        agents.complete(lease, agent, &archive)
+    }
+}
+
+pub mod agent_fail {
+    use errors::*;
     use server::{Data, Params};
 
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {
+        pub agent: String,
+        /// Why the test couldn't be run
+        pub error: String,
+    }
+
+    /// Ends a lease whose test couldn't be run.
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-    pub fn handler(_data: &Data, params: Params) -> TestResults {
+    pub fn handler(req: Request, data: &Data, params: Params) -> Result<()> {
+        let lease = params.find("lease").unwrap();
+        let mut agents = data.agents.lock().unwrap();
+        agents.fail(lease, &req.agent, &req.error)
+    }
+}
+
+pub mod queue_list {
+    use errors::*;
+    use server::{Data, Params};
+    use server::queue::QueuedEx;
+
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(data: &Data, _params: Params) -> Result<Vec<QueuedEx>> {
+        Ok(data.queue.lock().unwrap().list())
+    }
+}
+
+pub mod queue_create {
+    use errors::*;
+    use ex::{self, ExCrateSelect, ExMode, ExOpts};
+    use server::{Data, Params};
+    use server::github::GitHubIssue;
+    use server::notify::{self, Event};
+    use server::queue::QueuedEx;
+    use server::targets::NotifyTarget;
+    use util;
+
+    /// Defines an experiment and queues it. The fields take the same
+    /// values as the arguments of `define-ex`.
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {
+        pub name: String,
+        pub toolchains: Vec<String>,
+        pub mode: Option<String>,
+        pub crate_select: Option<String>,
+        #[serde(default)]
+        pub targets: Vec<String>,
+        #[serde(default)]
+        pub priority: i32,
+        #[serde(default)]
+        pub notify: Vec<NotifyTarget>,
+        /// The share of tests that can't be run, from 0 to 1, above which
+        /// the targets in `notify` are alerted
+        pub error_threshold: Option<f64>,
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(req: Request, data: &Data, _params: Params) -> Result<QueuedEx> {
+        create(data, &req, None)
+    }
+
+    /// Defines and queues an experiment, for the issue `github` if it was
+    /// requested there.
+    pub fn create(data: &Data, req: &Request, github: Option<GitHubIssue>) -> Result<QueuedEx> {
+        // The name becomes a directory under work/ex
+        if !util::is_safe_name(&req.name) {
+            bail!(ErrorKind::BadRequest(format!("invalid experiment name {}, which may only \
+                                                 have ASCII letters, digits, '.', '_' and '-'",
+                                                req.name)));
+        }
+        let opts = parse_opts(req)
+            .chain_err(|| ErrorKind::BadRequest(format!("invalid experiment {}", req.name)))?;
+        for target in &req.notify {
+            if let NotifyTarget::Command(ref name) = *target {
+                if !data.commands.contains_key(name) {
+                    bail!(ErrorKind::BadRequest(format!("unknown notification command {}",
+                                                        name)));
+                }
+            }
+        }
+
+        // Defining can take a while, so the name is reserved rather than
+        // the queue locked meanwhile
+        data.queue.lock().unwrap().reserve(&req.name)?;
+        if let Err(e) = ex::define(opts) {
+            data.queue.lock().unwrap().release(&req.name);
+            return Err(e);
+        }
+        let mut queued = QueuedEx::new(&req.name, req.priority);
+        queued.github = github;
+        queued.notify = req.notify.clone();
+        queued.error_threshold = req.error_threshold;
+        let queued = data.queue.lock().unwrap().push(queued)?;
+        notify::send(data, &queued.name, &Event::Queued);
+        Ok(queued)
+    }
+
+    fn parse_opts(req: &Request) -> Result<ExOpts> {
+        if req.toolchains.len() != 2 {
+            bail!("experiments need two toolchains, not {}", req.toolchains.len());
+        }
+        if let Some(threshold) = req.error_threshold {
+            if threshold < 0.0 || threshold > 1.0 {
+                bail!("the error threshold must be from 0 to 1, not {}", threshold);
+            }
+        }
+        Ok(ExOpts {
+               name: req.name.clone(),
+               toolchains: req.toolchains
+                   .iter()
+                   .map(|tc| tc.parse())
+                   .collect::<Result<_>>()?,
+               mode: match req.mode {
+                   Some(ref mode) => mode.parse()?,
+                   None => ExMode::BuildAndTest,
+               },
+               crates: match req.crate_select {
+                   Some(ref crates) => crates.parse()?,
+                   None => ExCrateSelect::Demo,
+               },
+               targets: req.targets.clone(),
+           })
+    }
+}
+
+pub mod queue_cancel {
+    use errors::*;
+    use server::{Data, Params};
+    use server::notify::{self, Event};
+    use server::queue::QueuedEx;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {}
+
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(_req: Request, data: &Data, params: Params) -> Result<QueuedEx> {
         let ex_name = params.find("experiment").unwrap();
-        let ex = ex::Experiment::load(ex_name).unwrap();
-        generate_report(&ex, None).unwrap()
+        let cancelled = data.queue.lock().unwrap().cancel(ex_name)?;
+        notify::send(data, ex_name, &Event::Cancelled);
+        Ok(cancelled)
     }
 }
 
-pub mod ex_config {
-    use ex;
+pub mod queue_priority {
+    use errors::*;
     use server::{Data, Params};
+    use server::queue::QueuedEx;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {
+        pub priority: i32,
+    }
 
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-    pub fn handler(_data: &Data, params: Params) -> ex::Experiment {
+    pub fn handler(req: Request, data: &Data, params: Params) -> Result<QueuedEx> {
         let ex_name = params.find("experiment").unwrap();
-        ex::Experiment::load(ex_name).unwrap()
+        data.queue.lock().unwrap().set_priority(ex_name, req.priority)
+    }
+}
+
+pub mod github_webhook {
+    use errors::*;
+    use hyper::header::Headers;
+    use serde_json;
+    use server::{Data, Params};
+    use server::github::{self, GitHubIssue};
+    use std::str;
+    use std::sync::Arc;
+    use std::thread;
+    use util;
+
+    /// The parts of GitHub's issue_comment event that are used.
+    #[derive(Serialize, Deserialize)]
+    pub struct Request {
+        pub action: String,
+        pub comment: Comment,
+        pub issue: Issue,
+        pub repository: Repository,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Comment {
+        pub body: String,
+        pub user: User,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct User {
+        pub login: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Issue {
+        pub number: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Repository {
+        pub full_name: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Response {
+        /// Whether the event held a command for the bot
+        pub handled: bool,
+    }
+
+    /// Takes GitHub's webhook events, carrying out the commands in new
+    /// comments in the background and replying with comments.
+    #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+    pub fn handler(headers: &Headers,
+                   payload: Vec<u8>,
+                   data: Arc<Data>,
+                   _params: Params)
+                   -> Result<Response> {
+        let github = match data.github {
+            Some(ref github) => github.clone(),
+            None => bail!(ErrorKind::NotFound("GitHub configuration".into())),
+        };
+        github.verify(&payload, header(headers, "X-Hub-Signature"))?;
+        if header(headers, "X-GitHub-Event") != Some("issue_comment") {
+            return Ok(Response { handled: false });
+        }
+
+        let event: Request = serde_json::from_slice(&payload)
+            .chain_err(|| ErrorKind::BadRequest("invalid issue_comment event".into()))?;
+        if event.action != "created" {
+            return Ok(Response { handled: false });
+        }
+        let command = match github::parse_command(&github.config.bot_name, &event.comment.body) {
+            Some(command) => command,
+            None => return Ok(Response { handled: false }),
+        };
+
+        let user = event.comment.user.login;
+        let issue = GitHubIssue {
+            repo: event.repository.full_name,
+            number: event.issue.number,
+        };
+        info!("command from {} on {}#{}: {}",
+              user,
+              issue.repo,
+              issue.number,
+              event.comment.body);
+        // Defining experiments can take minutes, longer than GitHub waits
+        thread::spawn(move || {
+            let r = if !github.config.reviewers.contains(&user) {
+                Err(format!("@{} isn't allowed to give commands", user).into())
+            } else {
+                command.and_then(|command| github::run_command(&data, &issue, command))
+            };
+            if let Err(e) = r {
+                let message = format!("@{}: {}",
+                                      user,
+                                      e.iter()
+                                          .map(|e| e.to_string())
+                                          .collect::<Vec<_>>()
+                                          .join(": "));
+                if let Err(e) = github.comment(&issue, &message) {
+                    util::report_error(&e);
+                }
+            }
+        });
+
+        Ok(Response { handled: true })
+    }
+
+    fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+        headers
+            .get_raw(name)
+            .and_then(|raw| raw.one())
+            .and_then(|value| str::from_utf8(value).ok())
     }
 }
@@ -0,0 +1,223 @@
+// Bearer tokens for the server's APIs, with scopes limiting what each can
+// do, read from work/server/tokens.json. Every mutation is recorded in
+// work/server/audit.log.
+
+use chrono::UTC;
+use constant_time_eq::constant_time_eq;
+use dirs::SERVER_DIR;
+use errors::*;
+use file;
+use serde_json;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// The report page itself, open to everyone
+    Public,
+    /// Reading reports, progress and the queue
+    Read,
+    /// Queueing, cancelling and reprioritising experiments
+    Submit,
+    /// Claiming work and uploading results, for agents
+    Agent,
+    /// GitHub's webhook, which is checked by its signature instead
+    Webhook,
+}
+
+impl Scope {
+    /// Whether requests in the scope are mutations, to be audited.
+    pub fn is_audited(&self) -> bool {
+        match *self {
+            Scope::Public | Scope::Read => false,
+            Scope::Submit | Scope::Agent | Scope::Webhook => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Token {
+    /// Who holds the token, as recorded in the audit log
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Tokens {
+    /// Whether reports can be read without a token
+    #[serde(default = "default_public_reports")]
+    pub public_reports: bool,
+    #[serde(default)]
+    pub tokens: Vec<Token>,
+}
+
+fn default_public_reports() -> bool {
+    true
+}
+
+impl Default for Tokens {
+    fn default() -> Tokens {
+        Tokens {
+            public_reports: default_public_reports(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+fn tokens_file() -> PathBuf {
+    Path::new(SERVER_DIR).join("tokens.json")
+}
+
+fn audit_file() -> PathBuf {
+    Path::new(SERVER_DIR).join("audit.log")
+}
+
+impl Tokens {
+    /// Loads the tokens, of which there are none if there's no tokens
+    /// file, leaving only public reports.
+    pub fn load() -> Result<Tokens> {
+        let path = tokens_file();
+        if !path.exists() {
+            warn!("no {}, only reports will be served", path.display());
+            return Ok(Tokens::default());
+        }
+        let tokens = file::read_string(&path)?;
+        Ok(serde_json::from_str(&tokens).chain_err(|| format!("invalid {}", path.display()))?)
+    }
+
+    /// Checks that a request with `token` may use an API needing `scope`,
+    /// returning the name of the token's holder, if there was one.
+    pub fn authorize(&self, token: Option<&str>, scope: Scope) -> Result<Option<String>> {
+        if scope == Scope::Public || scope == Scope::Webhook {
+            return Ok(None);
+        }
+        let holder = match token {
+            Some(token) => {
+                match self.tokens
+                          .iter()
+                          .find(|t| constant_time_eq(t.token.as_bytes(), token.as_bytes())) {
+                    Some(holder) => holder,
+                    None => bail!(ErrorKind::Unauthorized("unknown token".into())),
+                }
+            }
+            None if scope == Scope::Read && self.public_reports => return Ok(None),
+            None => bail!(ErrorKind::Unauthorized("a bearer token is required".into())),
+        };
+        if !holder.scopes.contains(&scope) {
+            bail!(ErrorKind::Forbidden(format!("{} may not use {:?} APIs", holder.name, scope)));
+        }
+        Ok(Some(holder.name.clone()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the request was made, in RFC 3339
+    pub time: String,
+    /// The holder of the request's token, if it had a known one
+    pub holder: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// Appends a request to the audit log.
+pub fn audit(holder: Option<String>, method: String, path: String, status: u16) -> Result<()> {
+    let entry = AuditEntry {
+        time: UTC::now().to_rfc3339(),
+        holder: holder,
+        method: method,
+        path: path,
+        status: status,
+    };
+    fs::create_dir_all(SERVER_DIR)?;
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_file())?;
+    writeln!(log, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(public_reports: bool) -> Tokens {
+        Tokens {
+            public_reports: public_reports,
+            tokens: vec![Token {
+                             name: "alice".to_string(),
+                             token: "secret-a".to_string(),
+                             scopes: vec![Scope::Read, Scope::Submit],
+                         },
+                         Token {
+                             name: "worker-1".to_string(),
+                             token: "secret-w".to_string(),
+                             scopes: vec![Scope::Agent],
+                         }],
+        }
+    }
+
+    fn is_unauthorized<T>(res: Result<T>) -> bool {
+        match res {
+            Err(Error(ErrorKind::Unauthorized(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn is_forbidden<T>(res: Result<T>) -> bool {
+        match res {
+            Err(Error(ErrorKind::Forbidden(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn skips_the_token_check_of_public_apis_and_webhooks() {
+        let tokens = tokens(false);
+        for &scope in &[Scope::Public, Scope::Webhook] {
+            assert_eq!(tokens.authorize(None, scope).unwrap(), None);
+            assert_eq!(tokens.authorize(Some("bogus"), scope).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn reads_without_a_token_only_with_public_reports() {
+        assert_eq!(tokens(true).authorize(None, Scope::Read).unwrap(), None);
+        assert!(is_unauthorized(tokens(false).authorize(None, Scope::Read)));
+        assert!(is_unauthorized(tokens(true).authorize(None, Scope::Submit)));
+        assert!(is_unauthorized(tokens(true).authorize(None, Scope::Agent)));
+    }
+
+    #[test]
+    fn checks_tokens_and_their_scopes() {
+        let tokens = tokens(true);
+        assert!(is_unauthorized(tokens.authorize(Some("bogus"), Scope::Read)));
+        assert!(is_unauthorized(tokens.authorize(Some(""), Scope::Read)));
+        assert!(is_unauthorized(tokens.authorize(Some("secret-"), Scope::Submit)));
+
+        assert!(is_forbidden(tokens.authorize(Some("secret-a"), Scope::Agent)));
+        assert!(is_forbidden(tokens.authorize(Some("secret-w"), Scope::Read)));
+        assert!(is_forbidden(tokens.authorize(Some("secret-w"), Scope::Submit)));
+
+        assert_eq!(tokens.authorize(Some("secret-a"), Scope::Submit).unwrap(),
+                   Some("alice".to_string()));
+        assert_eq!(tokens.authorize(Some("secret-a"), Scope::Read).unwrap(),
+                   Some("alice".to_string()));
+        assert_eq!(tokens.authorize(Some("secret-w"), Scope::Agent).unwrap(),
+                   Some("worker-1".to_string()));
+    }
+
+    #[test]
+    fn audits_mutations() {
+        assert!(!Scope::Public.is_audited());
+        assert!(!Scope::Read.is_audited());
+        assert!(Scope::Submit.is_audited());
+        assert!(Scope::Agent.is_audited());
+        assert!(Scope::Webhook.is_audited());
+    }
+}
@@ -0,0 +1,588 @@
+// Commands to the server in GitHub comments, such as "@cargobomb run beta
+// vs nightly on rdeps of serde", received through a webhook and answered
+// with comments. Configured in work/server/github.json.
+
+use constant_time_eq::constant_time_eq;
+use dirs::SERVER_DIR;
+use errors::*;
+use ex::{self, Experiment};
+use file;
+use hmac::{Hmac, Mac};
+use progress::Progress;
+use reqwest;
+use reqwest::header::{Authorization, UserAgent};
+use s3;
+use serde_json;
+use server::Data;
+use server::api::queue_create;
+use server::notify::{self, Event, Notifier};
+use server::queue::{ExStatus, QueuedEx};
+use sha1::Sha1;
+use std::path::{Path, PathBuf};
+
+/// An issue or pull request, which share their numbering.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct GitHubIssue {
+    /// The repository, e.g. "rust-lang/rust"
+    pub repo: String,
+    pub number: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GitHubConfig {
+    /// The user the bot comments as, and is mentioned as in commands
+    #[serde(default = "default_bot_name")]
+    pub bot_name: String,
+    /// The GitHub API, which can be replaced by a stand-in for testing
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// The bot's API token
+    pub token: String,
+    /// The secret the webhook is configured with
+    pub webhook_secret: String,
+    /// Where the server can be reached, for links to reports
+    pub server_url: String,
+    /// The users whose commands are followed
+    pub reviewers: Vec<String>,
+}
+
+fn default_bot_name() -> String {
+    "cargobomb".to_string()
+}
+
+fn default_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+fn config_file() -> PathBuf {
+    Path::new(SERVER_DIR).join("github.json")
+}
+
+impl GitHubConfig {
+    /// Loads the configuration, if there is any.
+    pub fn load() -> Result<Option<GitHubConfig>> {
+        let path = config_file();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config = file::read_string(&path)?;
+        Ok(Some(serde_json::from_str(&config)
+                    .chain_err(|| format!("invalid {}", path.display()))?))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Defines and queues an experiment, with values as taken by
+    /// `define-ex`
+    Run {
+        toolchains: Vec<String>,
+        crates: Option<String>,
+        mode: Option<String>,
+    },
+    /// Replies with the status of the issue's experiments
+    Status,
+    /// Cancels the issue's latest unfinished experiment
+    Cancel,
+}
+
+const USAGE: &'static str = "commands are `run <toolchain> vs <toolchain> [on <crate-select> | on \
+                             rdeps of <crate>] [mode <mode>]`, `status` and `cancel`";
+
+/// Finds a command to the bot in a comment, which is the rest of the
+/// first line that mentions it.
+pub fn parse_command(bot_name: &str, comment: &str) -> Option<Result<Command>> {
+    let mention = format!("@{}", bot_name);
+    for line in comment.lines() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if let Some(i) = words.iter().position(|w| *w == mention) {
+            return Some(parse_words(&words[i + 1..]));
+        }
+    }
+    None
+}
+
+fn parse_words(words: &[&str]) -> Result<Command> {
+    match words.split_first() {
+        Some((&"run", args)) => parse_run(args),
+        Some((&"status", rest)) if rest.is_empty() => Ok(Command::Status),
+        Some((&"cancel", rest)) if rest.is_empty() => Ok(Command::Cancel),
+        _ => bail!("unknown command; {}", USAGE),
+    }
+}
+
+fn parse_run(args: &[&str]) -> Result<Command> {
+    if args.len() < 3 || args[1] != "vs" {
+        bail!("expected two toolchains; {}", USAGE);
+    }
+    let mut crates = None;
+    let mut mode = None;
+    let mut rest = args[3..].iter();
+    while let Some(word) = rest.next() {
+        match *word {
+            "on" => {
+                crates = match rest.next() {
+                    Some(&"rdeps") => {
+                        match (rest.next(), rest.next()) {
+                            (Some(&"of"), Some(krate)) => Some(format!("rdeps:{}", krate)),
+                            _ => bail!("expected `rdeps of <crate>`; {}", USAGE),
+                        }
+                    }
+                    Some(select) => Some(select.to_string()),
+                    None => bail!("expected crates; {}", USAGE),
+                }
+            }
+            "mode" => {
+                mode = match rest.next() {
+                    Some(mode) => Some(mode.to_string()),
+                    None => bail!("expected a mode; {}", USAGE),
+                }
+            }
+            word => bail!("unexpected `{}`; {}", word, USAGE),
+        }
+    }
+
+    Ok(Command::Run {
+           toolchains: vec![args[0].to_string(), args[2].to_string()],
+           crates: crates,
+           mode: mode,
+       })
+}
+
+pub struct GitHub {
+    pub config: GitHubConfig,
+    client: reqwest::Client,
+}
+
+impl GitHub {
+    pub fn new(config: GitHubConfig) -> Result<GitHub> {
+        Ok(GitHub {
+               config: config,
+               client: reqwest::Client::new()?,
+           })
+    }
+
+    /// Checks that a webhook's payload was signed with the secret, as
+    /// given in its X-Hub-Signature header.
+    pub fn verify(&self, payload: &[u8], signature: Option<&str>) -> Result<()> {
+        let mut mac = Hmac::<Sha1>::new(self.config.webhook_secret.as_bytes());
+        mac.input(payload);
+        let expected = format!("sha1={}", s3::to_hex(mac.result().code()));
+        match signature {
+            Some(signature) if constant_time_eq(signature.as_bytes(), expected.as_bytes()) => {
+                Ok(())
+            }
+            _ => bail!(ErrorKind::Unauthorized("invalid webhook signature".into())),
+        }
+    }
+
+    pub fn comment(&self, issue: &GitHubIssue, body: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Comment<'a> {
+            body: &'a str,
+        }
+
+        let url = format!("{}/repos/{}/issues/{}/comments",
+                          self.config.api_url.trim_right_matches('/'),
+                          issue.repo,
+                          issue.number);
+        let res = self.client
+            .post(url.as_str())
+            .header(Authorization(format!("token {}", self.config.token)))
+            .header(UserAgent(self.config.bot_name.clone()))
+            .json(&Comment { body: body })
+            .send()?;
+        if !res.status().is_success() {
+            bail!("unable to comment on {}#{}: {}",
+                  issue.repo,
+                  issue.number,
+                  res.status());
+        }
+        Ok(())
+    }
+
+    /// The served report page of an experiment.
+    fn report_url(&self, ex_name: &str) -> String {
+        notify::report_url(&self.config.server_url, ex_name)
+    }
+
+    /// The comment announcing an event of `ex`, whose configuration is
+    /// read with `load` if it's needed.
+    fn message<F>(&self, ex: &QueuedEx, event: &Event, load: F) -> Result<String>
+        where F: FnOnce(&str) -> Result<Experiment>
+    {
+        Ok(match *event {
+               Event::Queued => {
+                   let config = load(&ex.name)?;
+                   let toolchains = config
+                       .toolchains
+                       .iter()
+                       .map(|tc| format!("`{}`", tc.to_string()))
+                       .collect::<Vec<_>>();
+                   format!("Experiment **{}** queued: {} on {} crates.",
+                           ex.name,
+                           toolchains.join(" vs "),
+                           config.crates.len())
+               }
+               Event::Prepared => {
+                   format!("Experiment **{}** is running; follow it at {}",
+                           ex.name,
+                           self.report_url(&ex.name))
+               }
+               Event::Done => {
+                   format!("Experiment **{}** is done: {}",
+                           ex.name,
+                           self.report_url(&ex.name))
+               }
+               Event::Failed(ref e) => format!("Experiment **{}** failed: {}", ex.name, e),
+               Event::Cancelled => format!("Experiment **{}** was cancelled.", ex.name),
+               Event::ErrorRate(rate) => {
+                   format!("Experiment **{}**: {:.0}% of tests couldn't be run so far.",
+                           ex.name,
+                           rate * 100.0)
+               }
+           })
+    }
+}
+
+impl Notifier for GitHub {
+    fn notify(&self, ex: &QueuedEx, event: &Event) -> Result<()> {
+        let issue = match ex.github {
+            Some(ref issue) => issue,
+            None => return Ok(()),
+        };
+        let message = self.message(ex, event, Experiment::load)?;
+        self.comment(issue, &message)
+    }
+}
+
+/// Carries out a command from a comment on `issue`.
+pub fn run_command(data: &Data, issue: &GitHubIssue, command: Command) -> Result<()> {
+    match command {
+        Command::Run {
+            toolchains,
+            crates,
+            mode,
+        } => {
+            let req = queue_create::Request {
+                name: next_name(data, issue),
+                toolchains: toolchains,
+                mode: mode,
+                crate_select: crates,
+                targets: Vec::new(),
+                priority: 0,
+                notify: Vec::new(),
+                error_threshold: None,
+            };
+            queue_create::create(data, &req, Some(issue.clone()))?;
+        }
+        Command::Status => {
+            let mut lines = Vec::new();
+            for ex in issue_experiments(data, issue) {
+                let progress = match Progress::load(&ex.name)? {
+                    Some(ref p) if ex.status == ExStatus::Running => {
+                        format!(", {} of {} tests done", p.done(), p.total())
+                    }
+                    _ => String::new(),
+                };
+                lines.push(format!("* **{}**: {}{}", ex.name, ex.status.to_str(), progress));
+            }
+            if lines.is_empty() {
+                bail!("there are no experiments for this issue");
+            }
+            reply(data, issue, &lines.join("\n"))?;
+        }
+        Command::Cancel => {
+            let ex = issue_experiments(data, issue)
+                .into_iter()
+                .rev()
+                .find(|ex| match ex.status {
+                          ExStatus::Done | ExStatus::Failed | ExStatus::Cancelled => false,
+                          _ => true,
+                      });
+            let ex = match ex {
+                Some(ex) => ex,
+                None => bail!("there is no unfinished experiment to cancel"),
+            };
+            data.queue.lock().unwrap().cancel(&ex.name)?;
+            notify::send(data, &ex.name, &Event::Cancelled);
+        }
+    }
+
+    Ok(())
+}
+
+/// Comments on an issue, if GitHub is configured.
+pub fn reply(data: &Data, issue: &GitHubIssue, message: &str) -> Result<()> {
+    match data.github {
+        Some(ref github) => github.comment(issue, message),
+        None => Ok(()),
+    }
+}
+
+/// The experiments requested from an issue, oldest first.
+fn issue_experiments(data: &Data, issue: &GitHubIssue) -> Vec<QueuedEx> {
+    let mut experiments = data.queue
+        .lock()
+        .unwrap()
+        .list()
+        .into_iter()
+        .filter(|ex| ex.github.as_ref() == Some(issue))
+        .collect::<Vec<_>>();
+    experiments.sort_by(|a, b| a.created.cmp(&b.created));
+    experiments
+}
+
+/// Names an issue's next experiment "pr-<owner>-<repo>-<number>", with a
+/// suffix for all but the first.
+fn next_name(data: &Data, issue: &GitHubIssue) -> String {
+    // The queue is only locked to list it, not while looking on disk
+    let queued = data.queue
+        .lock()
+        .unwrap()
+        .list()
+        .into_iter()
+        .map(|ex| ex.name)
+        .collect::<Vec<_>>();
+    experiment_name(issue,
+                    |name| queued.iter().any(|q| q == name) || ex::ex_dir(name).exists())
+}
+
+/// The first name for an experiment of `issue` that isn't `taken`.
+fn experiment_name<F>(issue: &GitHubIssue, taken: F) -> String
+    where F: Fn(&str) -> bool
+{
+    let repo = issue
+        .repo
+        .chars()
+        .map(|c| match c {
+                 'A'...'Z' | 'a'...'z' | '0'...'9' | '_' | '-' => c,
+                 _ => '-',
+             })
+        .collect::<String>();
+    let base = format!("pr-{}-{}", repo, issue.number);
+    (1..)
+        .map(|n| if n == 1 {
+                 base.clone()
+             } else {
+                 format!("{}-{}", base, n)
+             })
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ex::ExMode;
+    use lists::Crate;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use toolchain::Toolchain;
+
+    fn run(toolchains: [&str; 2], crates: Option<&str>, mode: Option<&str>) -> Command {
+        Command::Run {
+            toolchains: toolchains.iter().map(|t| t.to_string()).collect(),
+            crates: crates.map(|c| c.to_string()),
+            mode: mode.map(|m| m.to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        let comment = "Looks good, but let's check.\n\
+                       @cargobomb run beta vs nightly on rdeps of serde mode check-only";
+        assert_eq!(parse_command("cargobomb", comment).unwrap().unwrap(),
+                   run(["beta", "nightly"], Some("rdeps:serde"), Some("check-only")));
+        assert_eq!(parse_command("cargobomb", "@cargobomb run beta vs nightly on top-100")
+                       .unwrap()
+                       .unwrap(),
+                   run(["beta", "nightly"], Some("top-100"), None));
+        assert_eq!(parse_command("cargobomb", "@cargobomb status").unwrap().unwrap(),
+                   Command::Status);
+        assert_eq!(parse_command("cargobomb", "  @cargobomb cancel").unwrap().unwrap(),
+                   Command::Cancel);
+    }
+
+    #[test]
+    fn ignores_comments_without_mentions() {
+        assert!(parse_command("cargobomb", "cargobomb run beta vs nightly").is_none());
+        assert!(parse_command("cargobomb", "@cargobomb-test status").is_none());
+        assert!(parse_command("other", "@cargobomb status").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        for comment in &["@cargobomb",
+                         "@cargobomb frobnicate",
+                         "@cargobomb status please",
+                         "@cargobomb run beta",
+                         "@cargobomb run beta and nightly",
+                         "@cargobomb run beta vs nightly on",
+                         "@cargobomb run beta vs nightly on rdeps serde",
+                         "@cargobomb run beta vs nightly mode",
+                         "@cargobomb run beta vs nightly quickly"] {
+            assert!(parse_command("cargobomb", comment).unwrap().is_err(),
+                    "{} was accepted",
+                    comment);
+        }
+    }
+
+    fn config() -> GitHubConfig {
+        GitHubConfig {
+            bot_name: default_bot_name(),
+            api_url: default_api_url(),
+            token: "bot-token".to_string(),
+            webhook_secret: String::new(),
+            server_url: "http://localhost:2346".to_string(),
+            reviewers: Vec::new(),
+        }
+    }
+
+    fn github(secret: &str) -> GitHub {
+        GitHub::new(GitHubConfig {
+                        webhook_secret: secret.to_string(),
+                        ..config()
+                    })
+                .unwrap()
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let github = github("It's a Secret to Everybody");
+        let signature = "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59";
+        assert!(github.verify(b"Hello, World!", Some(signature)).is_ok());
+        assert!(github.verify(b"Hello, World?", Some(signature)).is_err());
+        assert!(github.verify(b"Hello, World!", None).is_err());
+        assert!(github("another secret")
+                    .verify(b"Hello, World!", Some(signature))
+                    .is_err());
+    }
+
+    #[test]
+    fn names_experiments_by_repo_and_number() {
+        let issue = GitHubIssue {
+            repo: "rust-lang/rust.vim".to_string(),
+            number: 1234,
+        };
+        assert_eq!(experiment_name(&issue, |_| false), "pr-rust-lang-rust-vim-1234");
+        assert_eq!(experiment_name(&issue, |name| name == "pr-rust-lang-rust-vim-1234"),
+                   "pr-rust-lang-rust-vim-1234-2");
+    }
+
+    pub struct Received {
+        pub method: String,
+        pub path: String,
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    /// Starts an HTTP server on a local port that answers every request
+    /// with `status`, e.g. "201 Created", returning its URL and the
+    /// requests it receives.
+    pub fn stand_in(status: &'static str) -> (String, Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || for stream in listener.incoming() {
+                          let mut stream = stream.unwrap();
+                          let received = {
+                              let mut reader = BufReader::new(&stream);
+                              let mut request_line = String::new();
+                              reader.read_line(&mut request_line).unwrap();
+                              let mut parts = request_line.split_whitespace();
+                              let method = parts.next().unwrap().to_string();
+                              let path = parts.next().unwrap().to_string();
+
+                              let mut headers = HashMap::new();
+                              loop {
+                                  let mut line = String::new();
+                                  reader.read_line(&mut line).unwrap();
+                                  let line = line.trim_right();
+                                  if line.is_empty() {
+                                      break;
+                                  }
+                                  let i = line.find(':').unwrap();
+                                  headers.insert(line[..i].to_lowercase(),
+                                                 line[i + 1..].trim().to_string());
+                              }
+                              let len = headers
+                                  .get("content-length")
+                                  .map_or(0, |len| len.parse().unwrap());
+                              let mut body = vec![0; len];
+                              reader.read_exact(&mut body).unwrap();
+                              Received {
+                                  method: method,
+                                  path: path,
+                                  headers: headers,
+                                  body: body,
+                              }
+                          };
+                          write!(stream,
+                                 "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                                 status)
+                                  .unwrap();
+                          tx.send(received).unwrap();
+                      });
+        (api_url, rx)
+    }
+
+    #[test]
+    fn comments_on_events() {
+        // A stand-in for GitHub's API
+        let (api_url, requests) = stand_in("201 Created");
+        let github = GitHub::new(GitHubConfig {
+                                     api_url: api_url + "/",
+                                     ..config()
+                                 })
+                .unwrap();
+        let issue = GitHubIssue {
+            repo: "rust-lang/rust".to_string(),
+            number: 1234,
+        };
+        let mut ex = QueuedEx::new("pr-rust-lang-rust-1234", 0);
+        ex.github = Some(issue.clone());
+        let config = Experiment {
+            name: ex.name.clone(),
+            crates: vec![Crate::Version {
+                             name: "serde".to_string(),
+                             version: "1.0.0".to_string(),
+                         },
+                         Crate::Repo { url: "https://github.com/brson/hello-rs".to_string() }],
+            toolchains: vec![Toolchain::Dist("stable".to_string()),
+                             Toolchain::Dist("beta".to_string())],
+            mode: ExMode::BuildAndTest,
+            targets: Vec::new(),
+        };
+
+        // Queued experiments are described from their configuration on
+        // disk, so that part of `notify` is done here
+        let queued = github.message(&ex, &Event::Queued, |_| Ok(config)).unwrap();
+        github.comment(&issue, &queued).unwrap();
+        github.notify(&ex, &Event::Done).unwrap();
+        github
+            .notify(&ex, &Event::Failed("no space left on device".to_string()))
+            .unwrap();
+        // Experiments that weren't requested on GitHub aren't commented on
+        github.notify(&QueuedEx::new("other", 0), &Event::Done).unwrap();
+
+        let comments = ["Experiment **pr-rust-lang-rust-1234** queued: `stable` vs `beta` on 2 \
+                         crates.",
+                        "Experiment **pr-rust-lang-rust-1234** is done: \
+                         http://localhost:2346/static/report.html?ex=pr-rust-lang-rust-1234",
+                        "Experiment **pr-rust-lang-rust-1234** failed: no space left on device"];
+        for comment in &comments {
+            let req = requests.recv().unwrap();
+            assert_eq!(req.method, "POST");
+            assert_eq!(req.path, "/repos/rust-lang/rust/issues/1234/comments");
+            assert_eq!(req.headers["authorization"], "token bot-token");
+            assert_eq!(req.headers["user-agent"], "cargobomb");
+            let body: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+            assert_eq!(body["body"].as_str(), Some(*comment));
+        }
+        assert!(requests.try_recv().is_err());
+    }
+}
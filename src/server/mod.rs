@@ -1,8 +1,9 @@
 use arc_cell::ArcCell;
-use futures::{self, BoxFuture, Future, Stream};
+use errors::*;
+use futures::{self, BoxFuture, Future, Sink, Stream};
 use futures_cpupool::CpuPool;
-use hyper::{self, Get, Post, StatusCode};
-use hyper::header::{ContentLength, ContentType};
+use hyper::{self, Body, Chunk, Get, Post, StatusCode};
+use hyper::header::{Authorization, Bearer, ContentLength, ContentType, Headers};
 use hyper::server::{Http, Request, Response, Service};
 use route_recognizer::{Match, Params, Router};
 
@@ -10,18 +11,74 @@ use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json;
 use std::env;
+use std::io::Read;
 use std::net::SocketAddr;
 use std::str;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use util;
 
-mod api;
+mod agents;
+pub mod api;
+mod auth;
+mod github;
+mod notify;
+mod queue;
+mod scheduler;
+mod targets;
 
-pub struct Data;
+// The largest result archive agents may upload
+const MAX_UPLOAD_SIZE: u64 = 64 * 1024 * 1024;
+// The largest webhook payload accepted
+const MAX_WEBHOOK_SIZE: u64 = 1024 * 1024;
+// How much of a streamed response is read and sent at a time
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct Data {
+    pub queue: Mutex<queue::Queue>,
+    pub agents: Mutex<agents::Agents>,
+    pub tokens: auth::Tokens,
+    pub github: Option<Arc<github::GitHub>>,
+    /// The commands notification targets can name
+    pub commands: targets::Commands,
+    /// Where events of queued experiments are sent
+    pub notifiers: Vec<Arc<notify::Notifier>>,
+}
+
+impl Data {
+    /// Loads the experiment queue, API tokens, notification commands and
+    /// GitHub configuration from disk.
+    pub fn load() -> Result<Data> {
+        let github = match github::GitHubConfig::load()? {
+            Some(config) => Some(Arc::new(github::GitHub::new(config)?)),
+            None => None,
+        };
+        // The server's address in links to reports
+        let server_url = env::var("CARGOBOMB_URL")
+            .unwrap_or_else(|_| "http://localhost:2346".to_string());
+        let commands = targets::load_commands()?;
+        let mut notifiers: Vec<Arc<notify::Notifier>> =
+            vec![Arc::new(targets::Targets::new(&server_url, commands.clone())?)];
+        if let Some(ref github) = github {
+            notifiers.push(github.clone());
+        }
+
+        Ok(Data {
+               queue: Mutex::new(queue::Queue::load()?),
+               agents: Mutex::new(agents::Agents::default()),
+               tokens: auth::Tokens::load()?,
+               github: github,
+               commands: commands,
+               notifiers: notifiers,
+           })
+    }
+}
 
 type Handler =
     Box<Fn(&Server, Request, Params) -> BoxFuture<Response, hyper::Error> + Sync + Send + 'static>;
+// The scope a request's token needs for a route
+type Route = (auth::Scope, Handler);
 struct Server {
-    router: Router<Handler>,
+    router: Router<Route>,
     data: ArcCell<Data>,
     pool: CpuPool,
 }
@@ -32,17 +89,21 @@ impl Server {
                         params: Params,
                         handler: F)
                         -> <Server as Service>::Future
-        where F: FnOnce(&Data, Params) -> S,
+        where F: FnOnce(&Data, Params) -> Result<S>,
               S: Serialize
     {
         if *req.method() != Get {
             return self.error(StatusCode::BadRequest);
         };
         let data = self.data.get();
-        let result = handler(&data, params);
-        let response = Response::new()
-            .with_header(ContentType::json())
-            .with_body(serde_json::to_string(&result).unwrap());
+        let response = match handler(&data, params) {
+            Ok(result) => {
+                Response::new()
+                    .with_header(ContentType::json())
+                    .with_body(serde_json::to_string(&result).unwrap())
+            }
+            Err(e) => error_response(&e),
+        };
         futures::future::ok(response).boxed()
     }
 
@@ -64,20 +125,20 @@ impl Server {
                             params: Params,
                             handler: F)
                             -> <Server as Service>::Future
-        where F: FnOnce(D, &Data, Params) -> S + Send + 'static,
+        where F: FnOnce(D, &Data, Params) -> Result<S> + Send + 'static,
               D: DeserializeOwned,
               S: Serialize
     {
         if *req.method() != Post {
             return self.error(StatusCode::BadRequest);
         };
-        let length = req.headers()
-            .get::<ContentLength>()
-            .expect("content-length to exist")
-            .0;
+        let length = match req.headers().get::<ContentLength>() {
+            Some(length) => length.0,
+            None => return self.error(StatusCode::LengthRequired),
+        };
         if length > 10_000 {
             // 10 kB
-            return futures::future::err(hyper::Error::TooLarge).boxed();
+            return self.error(StatusCode::PayloadTooLarge);
         }
         let data = self.data.get();
         self.pool
@@ -94,29 +155,214 @@ impl Server {
                                 error!("failed to deserialize request {}: {:?}",
                                        String::from_utf8_lossy(&body),
                                        err);
-                                return Response::new()
-                                           .with_header(ContentType::plaintext())
-                                           .with_body(format!("Failed to deserialize request; {:?}",
-                                                              err));
+                                let e = Error::from(err)
+                                    .chain_err(|| {
+                                                   ErrorKind::BadRequest("invalid request"
+                                                                             .into())
+                                               });
+                                return error_response(&e);
                             }
                         };
-                        let result = handler(body, &data, params);
-                        Response::new()
-                            .with_header(ContentType::json())
-                            .with_body(serde_json::to_string(&result).unwrap())
+                        match handler(body, &data, params) {
+                            Ok(result) => {
+                                Response::new()
+                                    .with_header(ContentType::json())
+                                    .with_body(serde_json::to_string(&result).unwrap())
+                            }
+                            Err(e) => error_response(&e),
+                        }
                     })
             })
             .boxed()
     }
 
+    /// Like `handle_post`, but for large binary uploads.
+    fn handle_upload<F, S>(&self,
+                           req: Request,
+                           params: Params,
+                           handler: F)
+                           -> <Server as Service>::Future
+        where F: FnOnce(Vec<u8>, &Data, Params) -> Result<S> + Send + 'static,
+              S: Serialize
+    {
+        if *req.method() != Post {
+            return self.error(StatusCode::BadRequest);
+        };
+        let length = match req.headers().get::<ContentLength>() {
+            Some(length) => length.0,
+            None => return self.error(StatusCode::LengthRequired),
+        };
+        if length > MAX_UPLOAD_SIZE {
+            return self.error(StatusCode::PayloadTooLarge);
+        }
+        let data = self.data.get();
+        self.pool
+            .spawn_fn(move || {
+                req.body()
+                    .fold(Vec::new(), |mut acc, chunk| {
+                        acc.extend_from_slice(&*chunk);
+                        futures::future::ok::<_, <Self as Service>::Error>(acc)
+                    })
+                    .map(move |body| match handler(body, &data, params) {
+                             Ok(result) => {
+                                 Response::new()
+                                     .with_header(ContentType::json())
+                                     .with_body(serde_json::to_string(&result).unwrap())
+                             }
+                             Err(e) => error_response(&e),
+                         })
+            })
+            .boxed()
+    }
+
+    /// Like `handle_upload`, for webhooks, whose handlers check the
+    /// request's headers to tell where it's from, and may carry on working
+    /// with the data after responding.
+    fn handle_webhook<F, S>(&self,
+                            req: Request,
+                            params: Params,
+                            handler: F)
+                            -> <Server as Service>::Future
+        where F: FnOnce(&Headers, Vec<u8>, Arc<Data>, Params) -> Result<S> + Send + 'static,
+              S: Serialize
+    {
+        if *req.method() != Post {
+            return self.error(StatusCode::BadRequest);
+        };
+        let length = match req.headers().get::<ContentLength>() {
+            Some(length) => length.0,
+            None => return self.error(StatusCode::LengthRequired),
+        };
+        if length > MAX_WEBHOOK_SIZE {
+            return self.error(StatusCode::PayloadTooLarge);
+        }
+        let data = self.data.get();
+        let headers = req.headers().clone();
+        self.pool
+            .spawn_fn(move || {
+                req.body()
+                    .fold(Vec::new(), |mut acc, chunk| {
+                        acc.extend_from_slice(&*chunk);
+                        futures::future::ok::<_, <Self as Service>::Error>(acc)
+                    })
+                    .map(move |body| match handler(&headers, body, data, params) {
+                             Ok(result) => {
+                                 Response::new()
+                                     .with_header(ContentType::json())
+                                     .with_body(serde_json::to_string(&result).unwrap())
+                             }
+                             Err(e) => error_response(&e),
+                         })
+            })
+            .boxed()
+    }
+
+    /// Serves the bytes returned by the handler.
+    fn handle_download<F>(&self,
+                          req: Request,
+                          params: Params,
+                          handler: F)
+                          -> <Server as Service>::Future
+        where F: FnOnce(&Data, Params) -> Result<Vec<u8>>
+    {
+        if *req.method() != Get {
+            return self.error(StatusCode::BadRequest);
+        };
+        let data = self.data.get();
+        let response = match handler(&data, params) {
+            Ok(body) => {
+                Response::new()
+                    .with_header(ContentType::octet_stream())
+                    .with_body(body)
+            }
+            Err(e) => error_response(&e),
+        };
+        futures::future::ok(response).boxed()
+    }
+
+    /// Streams the text read by the handler, so that large logs needn't be
+    /// held in memory.
+    fn handle_stream<F>(&self,
+                        req: Request,
+                        params: Params,
+                        handler: F)
+                        -> <Server as Service>::Future
+        where F: FnOnce(&Data, Params) -> Result<Box<Read + Send>>
+    {
+        if *req.method() != Get {
+            return self.error(StatusCode::BadRequest);
+        };
+        let data = self.data.get();
+        let mut reader = match handler(&data, params) {
+            Ok(reader) => reader,
+            Err(e) => return futures::future::ok(error_response(&e)).boxed(),
+        };
+        let (mut tx, body) = Body::pair();
+        // Streamed on the pool, so that many slow clients can't start more
+        // threads than it has
+        self.pool
+            .spawn_fn(move || {
+                let mut buf = vec![0; STREAM_CHUNK_SIZE];
+                loop {
+                    let chunk = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => Ok(Chunk::from(buf[..n].to_vec())),
+                        Err(e) => Err(hyper::Error::Io(e)),
+                    };
+                    let failed = chunk.is_err();
+                    tx = match tx.send(chunk).wait() {
+                        Ok(tx) => tx,
+                        // The client has gone away
+                        Err(_) => break,
+                    };
+                    if failed {
+                        break;
+                    }
+                }
+                Ok::<(), ()>(())
+            })
+            .forget();
+        let response = Response::new()
+            .with_header(ContentType::plaintext())
+            .with_body(body);
+        futures::future::ok(response).boxed()
+    }
+
     fn error(&self, status: StatusCode) -> <Server as Service>::Future {
-        futures::future::ok(Response::new()
-                                .with_header(ContentType::html())
-                                .with_status(status))
-                .boxed()
+        let error = status.canonical_reason().unwrap_or("error").to_string();
+        futures::future::ok(json_error(status, error)).boxed()
     }
 }
 
+/// The response for an error from a handler: 404 for things that don't
+/// exist, 400 for invalid requests, 401 and 403 for requests without
+/// the right token, and 500 for the rest.
+fn error_response(e: &Error) -> Response {
+    let status = match *e.kind() {
+        ErrorKind::NotFound(_) => StatusCode::NotFound,
+        ErrorKind::BadRequest(_) => StatusCode::BadRequest,
+        ErrorKind::Unauthorized(_) => StatusCode::Unauthorized,
+        ErrorKind::Forbidden(_) => StatusCode::Forbidden,
+        _ => {
+            util::report_error(e);
+            StatusCode::InternalServerError
+        }
+    };
+    let error = e.iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(": ");
+    json_error(status, error)
+}
+
+fn json_error(status: StatusCode, error: String) -> Response {
+    let body = api::error::Response { error: error };
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::json())
+        .with_body(serde_json::to_string(&body).unwrap())
+}
+
 impl Service for Server {
     type Request = Request;
     type Response = Response;
@@ -126,53 +372,161 @@ impl Service for Server {
     fn call(&self, req: Request) -> Self::Future {
         info!("handling: req.path()={:?}", req.path());
 
-        match self.router.recognize(req.path()) {
-            Ok(Match { handler, params }) => handler(self, req, params),
-            Err(_) => self.error(StatusCode::NotFound),
-        }
+        let (scope, handler, params) = match self.router.recognize(req.path()) {
+            Ok(Match { handler: &(scope, ref handler), params }) => (scope, handler, params),
+            Err(_) => return self.error(StatusCode::NotFound),
+        };
 
+        let token = req.headers()
+            .get::<Authorization<Bearer>>()
+            .map(|auth| auth.0.token.clone());
+        let holder = self.data
+            .get()
+            .tokens
+            .authorize(token.as_ref().map(|t| &t[..]), scope);
+        let audited = if !scope.is_audited() {
+            None
+        } else {
+            Some((holder.as_ref().ok().and_then(|h| h.clone()),
+                  req.method().to_string(),
+                  req.path().to_string()))
+        };
 
+        let response = match holder {
+            Ok(_) => handler(self, req, params),
+            Err(e) => futures::future::ok(error_response(&e)).boxed(),
+        };
+        match audited {
+            Some((holder, method, path)) => {
+                response
+                    .map(move |res| {
+                             let status = u16::from(res.status());
+                             if let Err(e) = auth::audit(holder, method, path, status) {
+                                 util::report_error(&e);
+                             }
+                             res
+                         })
+                    .boxed()
+            }
+            None => response,
+        }
     }
 }
 
 macro_rules! route {
-    ($router:ident, $path:expr, $method:ident, $($handler:tt)* ) => (
-        $router.add($path,
-            Box::new(|server: &Server, req, params| server.$method(req, params, $($handler)*)));
+    ($router:ident, $path:expr, $scope:ident, $method:ident, $($handler:tt)* ) => (
+        $router.add($path, (auth::Scope::$scope,
+            Box::new(|server: &Server, req, params| server.$method(req, params, $($handler)*))));
     )
 }
 
 pub fn start(data: Data) {
-    let mut router = Router::<Handler>::new();
-    route!(router, "/api/get", handle_get, api::get::handler);
-    route!(router, "/api/post", handle_post, api::post::handler);
+    let mut router = Router::<Route>::new();
+    route!(router,
+           "/api/ex",
+           Read,
+           handle_get,
+           api::ex_list::handler);
     route!(router,
            "/api/ex/:experiment/results",
+           Read,
            handle_get,
            api::ex_report::handler);
     route!(router,
            "/api/ex/:experiment/config",
+           Read,
            handle_get,
            api::ex_config::handler);
+    route!(router,
+           "/api/ex/:experiment/progress",
+           Read,
+           handle_get,
+           api::ex_progress::handler);
+    route!(router,
+           "/api/ex/:experiment/crate/:crate/:toolchain/result",
+           Read,
+           handle_get,
+           api::ex_crate_result::handler);
+    route!(router,
+           "/api/ex/:experiment/crate/:crate/:toolchain/log",
+           Read,
+           handle_stream,
+           api::ex_crate_log::handler);
+    route!(router,
+           "/api/ex/:experiment/shared",
+           Agent,
+           handle_download,
+           api::ex_shared::handler);
+    route!(router,
+           "/api/queue",
+           Read,
+           handle_get,
+           api::queue_list::handler);
+    route!(router,
+           "/api/queue/create",
+           Submit,
+           handle_post,
+           api::queue_create::handler);
+    route!(router,
+           "/api/queue/:experiment/cancel",
+           Submit,
+           handle_post,
+           api::queue_cancel::handler);
+    route!(router,
+           "/api/queue/:experiment/priority",
+           Submit,
+           handle_post,
+           api::queue_priority::handler);
+    route!(router,
+           "/api/agent/claim",
+           Agent,
+           handle_post,
+           api::agent_claim::handler);
+    route!(router,
+           "/api/agent/renew/:lease",
+           Agent,
+           handle_post,
+           api::agent_renew::handler);
+    route!(router,
+           "/api/agent/complete/:lease/:agent",
+           Agent,
+           handle_upload,
+           api::agent_complete::handler);
+    route!(router,
+           "/api/agent/fail/:lease",
+           Agent,
+           handle_post,
+           api::agent_fail::handler);
+    route!(router,
+           "/api/github/webhook",
+           Webhook,
+           handle_webhook,
+           api::github_webhook::handler);
     route!(router,
            "/static/report.html",
+           Public,
            handle_static,
            ContentType::html(),
            include_str!("../../static/report.html"));
     route!(router,
            "/static/report.js",
+           Public,
            handle_static,
            ContentType(mime!(Application / Javascript)),
            include_str!("../../static/report.js"));
     route!(router,
            "/static/report.css",
+           Public,
            handle_static,
            ContentType(mime!(Text / Css)),
            include_str!("../../static/report.css"));
 
+    let data = Arc::new(data);
+    scheduler::start(data.clone());
+
     let server = Arc::new(Server {
                               router,
-                              data: ArcCell::new(Arc::new(data)),
+                              data: ArcCell::new(data),
                               pool: CpuPool::new_num_cpus(),
                           });
     let mut server_address: SocketAddr = "0.0.0.0:2346".parse().unwrap();
@@ -0,0 +1,64 @@
+// Notifications of what happens to queued experiments, sent to whoever
+// asked for them by each of the server's notifiers
+
+use errors::*;
+use server::Data;
+use server::queue::QueuedEx;
+use url::form_urlencoded;
+use util;
+
+pub enum Event {
+    Queued,
+    /// The experiment was prepared, and is being run
+    Prepared,
+    /// The experiment's report was written
+    Done,
+    Failed(String),
+    Cancelled,
+    /// The share of tests that couldn't be run passed the experiment's
+    /// threshold
+    ErrorRate(f64),
+}
+
+pub trait Notifier: Send + Sync {
+    /// Tells whoever is interested in `ex` about an event, if anyone.
+    fn notify(&self, ex: &QueuedEx, event: &Event) -> Result<()>;
+}
+
+/// Sends an event about the experiment `ex_name` to every notifier.
+/// Failures are logged, as they shouldn't hold up the experiment.
+pub fn send(data: &Data, ex_name: &str, event: &Event) {
+    let ex = match data.queue.lock().unwrap().get(ex_name) {
+        Some(ex) => ex,
+        None => return,
+    };
+    for notifier in &data.notifiers {
+        if let Err(e) = notifier.notify(&ex, event) {
+            error!("unable to send notification for experiment {}", ex_name);
+            util::report_error(&e);
+        }
+    }
+}
+
+/// The served report page of an experiment on the server at `server_url`.
+pub fn report_url(server_url: &str, ex_name: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("ex", ex_name)
+        .finish();
+    format!("{}/static/report.html?{}",
+            server_url.trim_right_matches('/'),
+            query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_report_urls() {
+        assert_eq!(report_url("http://localhost:2346/", "pr-1234"),
+                   "http://localhost:2346/static/report.html?ex=pr-1234");
+        assert_eq!(report_url("http://localhost:2346", "a&b=c d"),
+                   "http://localhost:2346/static/report.html?ex=a%26b%3Dc+d");
+    }
+}
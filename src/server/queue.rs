@@ -0,0 +1,281 @@
+// The server's queue of experiments, persisted to disk so that it
+// survives restarts
+
+use chrono::UTC;
+use dirs::SERVER_DIR;
+use errors::*;
+use ex;
+use file;
+use serde_json;
+use server::github::GitHubIssue;
+use server::targets::NotifyTarget;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExStatus {
+    Queued,
+    Preparing,
+    Running,
+    Reporting,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl ExStatus {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            ExStatus::Queued => "queued",
+            ExStatus::Preparing => "preparing",
+            ExStatus::Running => "running",
+            ExStatus::Reporting => "reporting",
+            ExStatus::Done => "done",
+            ExStatus::Failed => "failed",
+            ExStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueuedEx {
+    pub name: String,
+    /// Experiments with higher priorities are prepared and run first
+    pub priority: i32,
+    pub status: ExStatus,
+    /// When the experiment was queued, in RFC 3339
+    pub created: String,
+    /// Why the experiment failed
+    pub error: Option<String>,
+    /// The issue or pull request the experiment was requested from
+    #[serde(default)]
+    pub github: Option<GitHubIssue>,
+    /// Where to send notifications of the experiment's progress
+    #[serde(default)]
+    pub notify: Vec<NotifyTarget>,
+    /// The share of tests that can't be run, from 0 to 1, above which
+    /// the notification targets are alerted
+    #[serde(default)]
+    pub error_threshold: Option<f64>,
+    /// Whether the targets have been alerted of the error rate
+    #[serde(default)]
+    pub error_alerted: bool,
+}
+
+impl QueuedEx {
+    /// A newly queued experiment, notifying no one.
+    pub fn new(name: &str, priority: i32) -> QueuedEx {
+        QueuedEx {
+            name: name.to_string(),
+            priority: priority,
+            status: ExStatus::Queued,
+            created: UTC::now().to_rfc3339(),
+            error: None,
+            github: None,
+            notify: Vec::new(),
+            error_threshold: None,
+            error_alerted: false,
+        }
+    }
+}
+
+pub struct Queue {
+    experiments: Vec<QueuedEx>,
+    /// The names of experiments being defined, to be queued next
+    reserved: Vec<String>,
+}
+
+fn queue_file() -> PathBuf {
+    Path::new(SERVER_DIR).join("queue.json")
+}
+
+impl Queue {
+    /// Loads the queue, requeueing the steps that were interrupted when the
+    /// server stopped.
+    pub fn load() -> Result<Queue> {
+        let path = queue_file();
+        let mut experiments: Vec<QueuedEx> = if path.exists() {
+            serde_json::from_str(&file::read_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+
+        for ex in &mut experiments {
+            ex.status = match ex.status {
+                ExStatus::Preparing => ExStatus::Queued,
+                ExStatus::Reporting => ExStatus::Running,
+                status => status,
+            };
+        }
+
+        Ok(Queue {
+               experiments: experiments,
+               reserved: Vec::new(),
+           })
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = queue_file();
+        fs::create_dir_all(SERVER_DIR)?;
+        // Write a new file and replace the old one, so that a crash doesn't
+        // leave it half-written
+        let tmp = path.with_extension("json.tmp");
+        file::write_string(&tmp, &serde_json::to_string(&self.experiments)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// The queued experiments, highest priority first.
+    pub fn list(&self) -> Vec<QueuedEx> {
+        let mut experiments = self.experiments.clone();
+        experiments.sort_by(|a, b| {
+                                b.priority
+                                    .cmp(&a.priority)
+                                    .then_with(|| a.created.cmp(&b.created))
+                            });
+        experiments
+    }
+
+    pub fn get(&self, name: &str) -> Option<QueuedEx> {
+        self.experiments.iter().find(|ex| ex.name == name).cloned()
+    }
+
+    /// The names of the experiments with a status, highest priority first.
+    pub fn with_status(&self, status: ExStatus) -> Vec<String> {
+        self.list()
+            .into_iter()
+            .filter(|ex| ex.status == status)
+            .map(|ex| ex.name)
+            .collect()
+    }
+
+    /// Claims a name for an experiment about to be defined and queued, so
+    /// that it can be defined without holding the queue's lock. Fails if
+    /// the name is taken.
+    pub fn reserve(&mut self, name: &str) -> Result<()> {
+        if self.experiments.iter().any(|e| e.name == name) ||
+           self.reserved.iter().any(|n| n == name) {
+            bail!(ErrorKind::BadRequest(format!("experiment {} is already queued", name)));
+        }
+        if ex::ex_dir(name).exists() {
+            bail!(ErrorKind::BadRequest(format!("experiment {} is already defined", name)));
+        }
+        self.reserved.push(name.to_string());
+        Ok(())
+    }
+
+    /// Gives up a name reserved with `reserve`.
+    pub fn release(&mut self, name: &str) {
+        self.reserved.retain(|n| n != name);
+    }
+
+    /// Adds an experiment, which must already be defined, and releases its
+    /// name if it was reserved.
+    pub fn push(&mut self, ex: QueuedEx) -> Result<QueuedEx> {
+        if self.experiments.iter().any(|e| e.name == ex.name) {
+            bail!(ErrorKind::BadRequest(format!("experiment {} is already queued", ex.name)));
+        }
+        self.release(&ex.name);
+        self.experiments.push(ex.clone());
+        self.save()?;
+        Ok(ex)
+    }
+
+    /// Stops an experiment that hasn't finished yet. Crates being tested
+    /// by agents are still recorded, but no more are handed out.
+    pub fn cancel(&mut self, name: &str) -> Result<QueuedEx> {
+        self.update(name, |ex| match ex.status {
+            ExStatus::Done | ExStatus::Failed | ExStatus::Cancelled => {
+                bail!(ErrorKind::BadRequest(format!("experiment {} has already finished",
+                                                    ex.name)))
+            }
+            _ => {
+                ex.status = ExStatus::Cancelled;
+                Ok(())
+            }
+        })
+    }
+
+    pub fn set_priority(&mut self, name: &str, priority: i32) -> Result<QueuedEx> {
+        self.update(name, |ex| {
+            ex.priority = priority;
+            Ok(())
+        })
+    }
+
+    /// Moves an experiment from one status to another, returning false if
+    /// it has left the first one in the meantime, e.g. by being cancelled.
+    pub fn transition(&mut self, name: &str, from: ExStatus, to: ExStatus) -> Result<bool> {
+        let mut moved = false;
+        self.update(name, |ex| {
+            if ex.status == from {
+                ex.status = to;
+                moved = true;
+            }
+            Ok(())
+        })?;
+        Ok(moved)
+    }
+
+    /// Marks an experiment as failed, returning false if it was cancelled
+    /// meanwhile.
+    pub fn fail(&mut self, name: &str, e: &Error) -> Result<bool> {
+        let mut failed = false;
+        self.update(name, |ex| {
+            if ex.status != ExStatus::Cancelled {
+                ex.status = ExStatus::Failed;
+                ex.error = Some(e.to_string());
+                failed = true;
+            }
+            Ok(())
+        })?;
+        Ok(failed)
+    }
+
+    /// Records that an experiment's error rate was alerted, returning
+    /// false if it already had been.
+    pub fn alert_errors(&mut self, name: &str) -> Result<bool> {
+        let mut first = false;
+        self.update(name, |ex| {
+            first = !ex.error_alerted;
+            ex.error_alerted = true;
+            Ok(())
+        })?;
+        Ok(first)
+    }
+
+    fn update<F>(&mut self, name: &str, f: F) -> Result<QueuedEx>
+        where F: FnOnce(&mut QueuedEx) -> Result<()>
+    {
+        let ex = {
+            let ex = match self.experiments.iter_mut().find(|ex| ex.name == name) {
+                Some(ex) => ex,
+                None => bail!(ErrorKind::NotFound(format!("queued experiment {}", name))),
+            };
+            f(ex)?;
+            ex.clone()
+        };
+        self.save()?;
+        Ok(ex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserves_names_once() {
+        let mut queue = Queue {
+            experiments: vec![QueuedEx::new("queued-test-ex", 0)],
+            reserved: Vec::new(),
+        };
+        assert!(queue.reserve("queued-test-ex").is_err());
+
+        queue.reserve("reserved-test-ex").unwrap();
+        assert!(queue.reserve("reserved-test-ex").is_err());
+        queue.release("reserved-test-ex");
+        queue.reserve("reserved-test-ex").unwrap();
+    }
+}
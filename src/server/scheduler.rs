@@ -0,0 +1,223 @@
+// The scheduler moves queued experiments through their lifecycle: it
+// prepares them one at a time, highest priority first, on a thread of
+// their own, then leaves them to the agents, and writes their reports once
+// every crate has a result
+
+use dirs::SERVER_DIR;
+use errors::*;
+use ex::{self, Experiment};
+use progress::Progress;
+use report::{self, ReportFormat, ReportOpts};
+use server::Data;
+use server::notify::{self, Event};
+use server::queue::ExStatus;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use util;
+
+// How often the scheduler looks for work
+const POLL_SECS: u64 = 30;
+// How many tests must be done before the error rate is judged
+const MIN_TESTS_FOR_ERROR_RATE: usize = 50;
+
+fn report_dir(ex_name: &str) -> PathBuf {
+    Path::new(SERVER_DIR).join("reports").join(ex_name)
+}
+
+// When the scheduler first saw each running experiment, and how many tests
+// were done then, for estimating when they'll be done
+type Starts = HashMap<String, (Instant, usize)>;
+
+pub fn start(data: Arc<Data>) {
+    thread::spawn(move || {
+        let mut starts = Starts::new();
+        let preparing = Arc::new(AtomicBool::new(false));
+        loop {
+            if let Err(e) = step(&data, &mut starts, &preparing) {
+                util::report_error(&e);
+            }
+            thread::sleep(Duration::from_secs(POLL_SECS));
+        }
+    });
+}
+
+fn step(data: &Arc<Data>, starts: &mut Starts, preparing: &Arc<AtomicBool>) -> Result<()> {
+    start_preparing(data, preparing)?;
+
+    let running = data.queue.lock().unwrap().with_status(ExStatus::Running);
+    starts.retain(|name, _| running.contains(name));
+    for name in running {
+        if !update_progress(data, &name, starts)? ||
+           !data.queue
+                .lock()
+                .unwrap()
+                .transition(&name, ExStatus::Running, ExStatus::Reporting)? {
+            continue;
+        }
+
+        info!("writing report for experiment {}", name);
+        let opts = ReportOpts {
+            format: ReportFormat::Html,
+            archive: false,
+            log_cap: report::DEFAULT_LOG_CAP,
+            report_url: None,
+        };
+        let r = report::gen(&name, &report_dir(&name), &opts);
+        finish(data, &name, ExStatus::Reporting, ExStatus::Done, r)?;
+    }
+
+    Ok(())
+}
+
+/// Prepares the next queued experiment on a thread of its own, so that
+/// the progress of running experiments is still recorded meanwhile, unless
+/// one is being prepared already. `preparing` is set while it is, which
+/// outlasts the experiment's status if it's cancelled while preparing.
+fn start_preparing(data: &Arc<Data>, preparing: &Arc<AtomicBool>) -> Result<()> {
+    if preparing.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let name = {
+        let mut queue = data.queue.lock().unwrap();
+        let name = match queue.with_status(ExStatus::Queued).into_iter().next() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        if !queue.transition(&name, ExStatus::Queued, ExStatus::Preparing)? {
+            return Ok(());
+        }
+        name
+    };
+
+    info!("preparing experiment {}", name);
+    preparing.store(true, Ordering::SeqCst);
+    let data = data.clone();
+    let preparing = preparing.clone();
+    thread::spawn(move || {
+        defer!{{
+            preparing.store(false, Ordering::SeqCst);
+        }}
+        let r = Experiment::load(&name).and_then(|ex| ex::prepare(&ex));
+        if let Err(e) = finish(&data, &name, ExStatus::Preparing, ExStatus::Running, r) {
+            util::report_error(&e);
+        }
+    });
+    Ok(())
+}
+
+/// Moves an experiment on to its next status once a step is over, or
+/// marks it as failed.
+fn finish(data: &Data, name: &str, from: ExStatus, to: ExStatus, r: Result<()>) -> Result<()> {
+    let event = match r {
+        Ok(()) => {
+            if !data.queue.lock().unwrap().transition(name, from, to)? {
+                return Ok(());
+            }
+            match to {
+                ExStatus::Running => Event::Prepared,
+                _ => Event::Done,
+            }
+        }
+        Err(e) => {
+            error!("experiment {} failed", name);
+            util::report_error(&e);
+            if !data.queue.lock().unwrap().fail(name, &e)? {
+                return Ok(());
+            }
+            Event::Failed(e.to_string())
+        }
+    };
+    notify::send(data, name, &event);
+    Ok(())
+}
+
+/// Records the progress of an experiment being run by agents, returning
+/// whether every crate has a result with every toolchain, or couldn't be
+/// tested.
+fn update_progress(data: &Data, ex_name: &str, starts: &mut Starts) -> Result<bool> {
+    let mut progress = Progress::from_results(&Experiment::load(ex_name)?)?;
+    {
+        let agents = data.agents.lock().unwrap();
+        agents.record_failures(ex_name, &mut progress);
+        progress.current = agents.current(ex_name);
+    }
+
+    let done = progress.done();
+    let &mut (start, done_at_start) = starts
+        .entry(ex_name.to_string())
+        .or_insert((Instant::now(), done));
+    if done > done_at_start {
+        let elapsed = Instant::now().duration_since(start).as_secs();
+        let secs_per_test = elapsed as f64 / (done - done_at_start) as f64;
+        progress.eta_secs = Some(((progress.total() - done) as f64 * secs_per_test) as u64);
+    }
+
+    progress.save(ex_name)?;
+    check_error_rate(data, ex_name, &progress)?;
+    Ok(done == progress.total())
+}
+
+/// Alerts the notifiers the first time the share of an experiment's tests
+/// that couldn't be run passes its threshold.
+fn check_error_rate(data: &Data, ex_name: &str, progress: &Progress) -> Result<()> {
+    let threshold = match data.queue
+              .lock()
+              .unwrap()
+              .get(ex_name)
+              .and_then(|ex| ex.error_threshold) {
+        Some(threshold) => threshold,
+        None => return Ok(()),
+    };
+    let rate = match error_rate(progress) {
+        Some(rate) => rate,
+        None => return Ok(()),
+    };
+    if rate > threshold && data.queue.lock().unwrap().alert_errors(ex_name)? {
+        info!("{:.0}% of tests of experiment {} couldn't be run",
+              rate * 100.0,
+              ex_name);
+        notify::send(data, ex_name, &Event::ErrorRate(rate));
+    }
+    Ok(())
+}
+
+/// The share of an experiment's tests that agents couldn't run, which
+/// `Agents::record_failures` counts as "error", once enough are done to
+/// judge it.
+fn error_rate(progress: &Progress) -> Option<f64> {
+    let done = progress.done();
+    if done < MIN_TESTS_FOR_ERROR_RATE {
+        return None;
+    }
+    let errors = progress.results.get("error").cloned().unwrap_or(0);
+    Some(errors as f64 / done as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use results::TestResult;
+    use toolchain::Toolchain;
+
+    #[test]
+    fn judges_the_error_rate_after_enough_tests() {
+        let tc = Toolchain::Dist("stable".into());
+        let mut progress = Progress::new(&[tc.clone()], 100);
+        for _ in 0..39 {
+            progress.record(&tc, Some(&TestResult::TestPass));
+        }
+        for _ in 0..10 {
+            progress.record(&tc, None);
+        }
+        assert_eq!(error_rate(&progress), None);
+
+        progress.record(&tc, None);
+        let rate = error_rate(&progress).unwrap();
+        assert_eq!(rate, 11.0 / 50.0);
+        assert!(rate > 0.2);
+    }
+}
@@ -0,0 +1,360 @@
+// Notification targets given when queueing an experiment: webhooks, which
+// are posted a JSON summary, and commands, such as one sending email, which
+// are fed a plain-text one. Commands are configured by the operator in
+// work/server/commands.json, and only named when queueing.
+
+use dirs::SERVER_DIR;
+use errors::*;
+use ex::Experiment;
+use file;
+use progress::Progress;
+use report;
+use reqwest;
+use serde_json;
+use server::notify::{self, Event, Notifier};
+use server::queue::QueuedEx;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use util;
+
+// How long a command may run before it's killed, as notifications are sent
+// from the scheduler's thread
+const COMMAND_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyTarget {
+    /// A URL the payload is posted to
+    Webhook(String),
+    /// The name of a command in commands.json, which is run with `sh -c`,
+    /// given the summary on stdin and the experiment and event in
+    /// `$CARGOBOMB_EXPERIMENT` and `$CARGOBOMB_EVENT`
+    Command(String),
+}
+
+/// The shell commands notification targets can name, by name.
+pub type Commands = BTreeMap<String, String>;
+
+fn commands_file() -> PathBuf {
+    Path::new(SERVER_DIR).join("commands.json")
+}
+
+/// Loads the commands, of which there are none if there's no commands
+/// file.
+pub fn load_commands() -> Result<Commands> {
+    let path = commands_file();
+    if !path.exists() {
+        return Ok(Commands::new());
+    }
+    let commands = file::read_string(&path)?;
+    Ok(serde_json::from_str(&commands).chain_err(|| format!("invalid {}", path.display()))?)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Payload {
+    pub experiment: String,
+    /// "prepared", "done", "failed" or "error-rate"
+    pub event: String,
+    pub report_url: String,
+    /// Tests done and in total
+    pub done: usize,
+    pub total: usize,
+    /// The number of crates with each comparison, e.g. "regressed", for
+    /// "done" and "error-rate"
+    pub comparisons: Option<BTreeMap<String, usize>>,
+    /// Why the experiment failed
+    pub error: Option<String>,
+    /// The share of tests that couldn't be run, for "error-rate"
+    pub error_rate: Option<f64>,
+}
+
+pub struct Targets {
+    server_url: String,
+    commands: Commands,
+    client: reqwest::Client,
+}
+
+impl Targets {
+    /// Targets of experiments on the server at `server_url`, for links to
+    /// reports.
+    pub fn new(server_url: &str, commands: Commands) -> Result<Targets> {
+        Ok(Targets {
+               server_url: server_url.to_string(),
+               commands: commands,
+               client: reqwest::Client::new()?,
+           })
+    }
+
+    fn payload(&self, ex: &QueuedEx, event: &Event) -> Result<Option<Payload>> {
+        let (name, error, error_rate) = match *event {
+            Event::Prepared => ("prepared", None, None),
+            Event::Done => ("done", None, None),
+            Event::Failed(ref e) => ("failed", Some(e.clone()), None),
+            Event::ErrorRate(rate) => ("error-rate", None, Some(rate)),
+            Event::Queued | Event::Cancelled => return Ok(None),
+        };
+
+        let config = Experiment::load(&ex.name)?;
+        let progress = Progress::from_results(&config)?;
+        let comparisons = match *event {
+            Event::Done | Event::ErrorRate(_) => {
+                let res = report::generate_report(&config, None)?;
+                Some(report::comparison_counts(&res))
+            }
+            _ => None,
+        };
+
+        Ok(Some(Payload {
+                    experiment: ex.name.clone(),
+                    event: name.to_string(),
+                    report_url: notify::report_url(&self.server_url, &ex.name),
+                    done: progress.done(),
+                    total: progress.total(),
+                    comparisons: comparisons,
+                    error: error,
+                    error_rate: error_rate,
+                }))
+    }
+
+    fn send(&self, target: &NotifyTarget, payload: &Payload) -> Result<()> {
+        match *target {
+            NotifyTarget::Webhook(ref url) => {
+                let res = self.client.post(url.as_str()).json(payload).send()?;
+                if !res.status().is_success() {
+                    bail!("webhook {} failed: {}", url, res.status());
+                }
+            }
+            NotifyTarget::Command(ref name) => {
+                let command = match self.commands.get(name) {
+                    Some(command) => command,
+                    None => bail!("no command {} in {}", name, commands_file().display()),
+                };
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("CARGOBOMB_EXPERIMENT", &payload.experiment)
+                    .env("CARGOBOMB_EVENT", &payload.event)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .chain_err(|| format!("unable to run `{}`", command))?;
+                // Dropping stdin closes it, ending the input. The command
+                // is waited for even if it didn't take all of it, so as
+                // not to leave a zombie
+                let written = child
+                    .stdin
+                    .take()
+                    .expect("the command's stdin is piped")
+                    .write_all(text_summary(payload).as_bytes());
+                let status = wait_for(&mut child, Duration::from_secs(COMMAND_TIMEOUT_SECS))
+                    .chain_err(|| format!("`{}` didn't finish", command))?;
+                if !status.success() {
+                    bail!("`{}` failed: {}", command, status);
+                }
+                written.chain_err(|| format!("unable to give `{}` the summary", command))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Notifier for Targets {
+    fn notify(&self, ex: &QueuedEx, event: &Event) -> Result<()> {
+        if ex.notify.is_empty() {
+            return Ok(());
+        }
+        let payload = match self.payload(ex, event)? {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        // One target failing shouldn't keep the others from hearing
+        for target in &ex.notify {
+            if let Err(e) = self.send(target, &payload) {
+                error!("unable to notify {:?} about experiment {}", target, ex.name);
+                util::report_error(&e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waits for `child` to exit, killing it if it's still running after
+/// `timeout`.
+fn wait_for(child: &mut Child, timeout: Duration) -> Result<ExitStatus> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            bail!(ErrorKind::Timeout("max time of", timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn text_summary(payload: &Payload) -> String {
+    let mut s = String::new();
+    // Writing to a String can't fail
+    let what = match payload.event.as_str() {
+        "prepared" => "is prepared and running",
+        "done" => "is done",
+        "failed" => "failed",
+        _ => "is running into errors",
+    };
+    writeln!(s, "Experiment {} {}.", payload.experiment, what).expect("");
+    if let Some(ref error) = payload.error {
+        writeln!(s, "Error: {}", error).expect("");
+    }
+    if let Some(rate) = payload.error_rate {
+        writeln!(s, "{:.0}% of tests couldn't be run.", rate * 100.0).expect("");
+    }
+    writeln!(s, "Tests done: {} of {}", payload.done, payload.total).expect("");
+    if let Some(ref comparisons) = payload.comparisons {
+        for (comparison, count) in comparisons {
+            writeln!(s, "{}: {}", comparison, count).expect("");
+        }
+    }
+    writeln!(s, "Report: {}", payload.report_url).expect("");
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::github::tests::stand_in;
+    use tempdir::TempDir;
+
+    fn payload() -> Payload {
+        let mut comparisons = BTreeMap::new();
+        comparisons.insert("regressed".to_string(), 2);
+        comparisons.insert("test-pass".to_string(), 40);
+        Payload {
+            experiment: "nightly-2017-06-01".to_string(),
+            event: "done".to_string(),
+            report_url: "http://localhost:2346/static/report.html?ex=nightly-2017-06-01"
+                .to_string(),
+            done: 84,
+            total: 84,
+            comparisons: Some(comparisons),
+            error: None,
+            error_rate: None,
+        }
+    }
+
+    fn targets(commands: Commands) -> Targets {
+        Targets::new("http://localhost:2346", commands).unwrap()
+    }
+
+    #[test]
+    fn summarizes_payloads() {
+        assert_eq!(text_summary(&payload()),
+                   "Experiment nightly-2017-06-01 is done.\n\
+                    Tests done: 84 of 84\n\
+                    regressed: 2\n\
+                    test-pass: 40\n\
+                    Report: http://localhost:2346/static/report.html?ex=nightly-2017-06-01\n");
+
+        let failed = Payload {
+            event: "failed".to_string(),
+            done: 0,
+            comparisons: None,
+            error: Some("no space left on device".to_string()),
+            ..payload()
+        };
+        assert_eq!(text_summary(&failed),
+                   "Experiment nightly-2017-06-01 failed.\n\
+                    Error: no space left on device\n\
+                    Tests done: 0 of 84\n\
+                    Report: http://localhost:2346/static/report.html?ex=nightly-2017-06-01\n");
+
+        let error_rate = Payload {
+            event: "error-rate".to_string(),
+            done: 20,
+            error_rate: Some(0.254),
+            ..payload()
+        };
+        assert!(text_summary(&error_rate).starts_with("Experiment nightly-2017-06-01 is \
+                                                       running into errors.\n\
+                                                       25% of tests couldn't be run.\n\
+                                                       Tests done: 20 of 84\n"));
+    }
+
+    #[test]
+    fn serializes_payloads() {
+        let json = serde_json::to_value(&payload()).unwrap();
+        assert_eq!(json["experiment"], "nightly-2017-06-01");
+        assert_eq!(json["event"], "done");
+        assert_eq!(json["done"], 84);
+        assert_eq!(json["total"], 84);
+        assert_eq!(json["comparisons"]["regressed"], 2);
+        assert!(json["error"].is_null());
+        assert!(json["error_rate"].is_null());
+    }
+
+    #[test]
+    fn posts_payloads_to_webhooks() {
+        let (url, requests) = stand_in("204 No Content");
+        let hook = NotifyTarget::Webhook(url + "/hooks/cargobomb");
+        targets(Commands::new()).send(&hook, &payload()).unwrap();
+
+        let req = requests.recv().unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/hooks/cargobomb");
+        assert_eq!(req.headers["content-type"], "application/json");
+        let sent: Payload = serde_json::from_slice(&req.body).unwrap();
+        assert_eq!(sent.experiment, "nightly-2017-06-01");
+        assert_eq!(sent.event, "done");
+        assert_eq!(sent.comparisons, payload().comparisons);
+
+        let (url, _requests) = stand_in("500 Internal Server Error");
+        let hook = NotifyTarget::Webhook(url);
+        assert!(targets(Commands::new()).send(&hook, &payload()).is_err());
+    }
+
+    #[test]
+    fn feeds_commands_the_summary() {
+        let dir = TempDir::new("cargobomb-targets").unwrap();
+        let out = dir.path().join("out");
+        let mut commands = Commands::new();
+        commands.insert("record".to_string(),
+                        format!("echo $CARGOBOMB_EXPERIMENT $CARGOBOMB_EVENT > {0}; cat >> {0}",
+                                out.display()));
+        commands.insert("fail".to_string(), "exit 3".to_string());
+        let targets = targets(commands);
+
+        targets
+            .send(&NotifyTarget::Command("record".to_string()), &payload())
+            .unwrap();
+        assert_eq!(file::read_string(&out).unwrap(),
+                   format!("nightly-2017-06-01 done\n{}", text_summary(&payload())));
+
+        assert!(targets
+                    .send(&NotifyTarget::Command("fail".to_string()), &payload())
+                    .is_err());
+        assert!(targets
+                    .send(&NotifyTarget::Command("missing".to_string()), &payload())
+                    .is_err());
+    }
+
+    #[test]
+    fn kills_commands_that_run_too_long() {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("exec sleep 60")
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        match wait_for(&mut child, Duration::from_millis(200)) {
+            Err(Error(ErrorKind::Timeout(..), _)) => (),
+            res => panic!("not killed: {:?}", res.map_err(|e| e.to_string())),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
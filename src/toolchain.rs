@@ -1,6 +1,6 @@
 use dirs::{CARGO_HOME, RUSTUP_HOME, TARGET_DIR, TOOLCHAIN_DIR};
 use dl;
-use docker;
+use docker::{self, RunOutput};
 use errors::*;
 use git;
 use run;
@@ -33,6 +33,26 @@ impl Toolchain {
 
         Ok(())
     }
+
+    /// Installs the standard library for each cross-compilation target.
+    pub fn install_targets(&self, targets: &[String]) -> Result<()> {
+        let toolchain_name = self.rustup_name();
+        for target in targets {
+            info!("installing target {} for toolchain {}", target, toolchain_name);
+            util::try_hard(|| {
+                               rustup_run(&rustup_exe(),
+                                          &["target", "add", "--toolchain", &toolchain_name,
+                                            target],
+                                          &[])
+                                       .chain_err(|| {
+                                                      format!("unable to install target {}",
+                                                              target)
+                                                  })
+                           })?;
+        }
+
+        Ok(())
+    }
 }
 
 impl ToString for Toolchain {
@@ -175,6 +195,30 @@ impl Toolchain {
             Toolchain::Repo { .. } => panic!(),
         }
     }
+
+    /// The toolchain's `rustc -V`, e.g.
+    /// "rustc 1.19.0-nightly (0418fa9d3 2017-05-20)".
+    pub fn rustc_version(&self) -> Result<String> {
+        self.tool_version("rustc")
+    }
+
+    /// The toolchain's `cargo -V`, e.g.
+    /// "cargo 0.20.0-nightly (397359840 2017-05-18)".
+    pub fn cargo_version(&self) -> Result<String> {
+        self.tool_version("cargo")
+    }
+
+    fn tool_version(&self, tool: &str) -> Result<String> {
+        let toolchain_name = self.rustup_name();
+        let (out, _) = run::run_capture(None,
+                                        &rustup_exe(),
+                                        &["run", &toolchain_name, tool, "-V"],
+                                        &[("CARGO_HOME", CARGO_HOME),
+                                          ("RUSTUP_HOME", RUSTUP_HOME)])?;
+        out.into_iter()
+            .next()
+            .ok_or_else(|| format!("no output from {} -V", tool).into())
+    }
 }
 
 pub fn ex_target_dir(ex_name: &str) -> PathBuf {
@@ -207,15 +251,52 @@ impl Toolchain {
                                source_dir: &Path,
                                args: &[&str])
                                -> Result<()> {
+        self.run_cargo_in_docker_measured(ex_name, source_dir, args)
+            .result
+    }
+
+    /// Like `run_cargo_in_docker`, but also reports the resources the
+    /// command consumed.
+    pub fn run_cargo_in_docker_measured(&self,
+                                        ex_name: &str,
+                                        source_dir: &Path,
+                                        args: &[&str])
+                                        -> RunOutput {
+        self.run_in_docker(ex_name, source_dir, args, false)
+    }
+
+    /// Like `run_cargo_in_docker_measured`, but also counts the
+    /// instructions the command retired, for compile-time experiments.
+    pub fn run_cargo_in_docker_counted(&self,
+                                       ex_name: &str,
+                                       source_dir: &Path,
+                                       args: &[&str])
+                                       -> RunOutput {
+        self.run_in_docker(ex_name, source_dir, args, true)
+    }
+
+    fn run_in_docker(&self,
+                     ex_name: &str,
+                     source_dir: &Path,
+                     args: &[&str],
+                     count_instructions: bool)
+                     -> RunOutput {
         let toolchain_name = self.rustup_name();
         let ex_target_dir = self.target_dir(ex_name);
 
-        fs::create_dir_all(&ex_target_dir)?;
+        if let Err(e) = fs::create_dir_all(&ex_target_dir) {
+            return RunOutput {
+                       result: Err(e.into()),
+                       usage: None,
+                       stdout: Vec::new(),
+                       stderr: Vec::new(),
+                   };
+        }
 
         let toolchain_arg = "+".to_string() + &toolchain_name;
         let mut full_args = vec!["cargo", &*toolchain_arg];
         full_args.extend_from_slice(args);
 
-        docker::run(source_dir, &ex_target_dir, &full_args)
+        docker::run(source_dir, &ex_target_dir, &full_args, count_instructions)
     }
 }
@@ -48,6 +48,16 @@ pub fn report_error(e: &Error) {
     }
 }
 
+/// Whether `name` can be used as a file name and in URLs as is: it has only
+/// ASCII letters, digits, `.`, `_` and `-`, and isn't `.` or `..`.
+pub fn is_safe_name(name: &str) -> bool {
+    let safe_char = |c| match c {
+        'A'...'Z' | 'a'...'z' | '0'...'9' | '.' | '_' | '-' => true,
+        _ => false,
+    };
+    !name.is_empty() && name != "." && name != ".." && name.chars().all(safe_char)
+}
+
 pub fn report_panic(e: &Any) {
     if let Some(e) = e.downcast_ref::<String>() {
         error!("panicked: {}", e);
@@ -122,3 +132,18 @@ pub fn copy_dir(src_dir: &Path, dest_dir: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_names() {
+        assert!(is_safe_name("nightly-2017-05-29.beta_1"));
+        assert!(!is_safe_name(""));
+        assert!(!is_safe_name(".."));
+        assert!(!is_safe_name("../x"));
+        assert!(!is_safe_name("a b"));
+        assert!(!is_safe_name("caf\u{e9}"));
+    }
+}
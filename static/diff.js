@@ -0,0 +1,44 @@
+window.onload = function() {
+    let diffReq = new XMLHttpRequest();
+    diffReq.addEventListener("load", function() {
+	begin(JSON.parse(diffReq.responseText));
+    });
+    diffReq.overrideMimeType("application/json");
+    diffReq.open("GET", "diff.json");
+    diffReq.send();
+};
+
+function begin(diff) {
+    document.getElementById("ex-a").textContent = diff.ex_a;
+    document.getElementById("ex-b").textContent = diff.ex_b;
+
+    insertSection("newly-regressed", "Newly regressed", "regressed", diff.newly_regressed);
+    insertSection("still-regressed", "Still regressed", "same-build-fail", diff.still_regressed);
+    insertSection("no-longer-regressed", "No longer regressed", "fixed", diff.no_longer_regressed);
+    insertSection("no-longer-tested", "No longer tested", "unknown", diff.no_longer_tested || []);
+}
+
+function insertSection(id, title, class_, crates) {
+    let sectionEl = document.getElementById(id);
+    let html = `<h2>${title} (${crates.length})</h2>`;
+
+    for (crate of crates) {
+	let error = crate.error ? escapeHtml(crate.error) : "";
+	html += `<div class="${class_}">
+	    <span>${escapeHtml(crate.name)}</span>
+	    <span class="error">${error}</span>
+	</div>`;
+    }
+
+    sectionEl.innerHTML = html;
+}
+
+// Escapes text for use in HTML, both as content and as attribute values
+function escapeHtml(s) {
+    return String(s)
+	.replace(/&/g, "&amp;")
+	.replace(/</g, "&lt;")
+	.replace(/>/g, "&gt;")
+	.replace(/"/g, "&quot;")
+	.replace(/'/g, "&#39;");
+}
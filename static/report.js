@@ -1,22 +1,109 @@
 let config = null;
 let results = null;
+// The experiment's API when the report is served by `serve-report`
+let api = null;
+
+// How often to poll the progress of a running experiment
+const PROGRESS_INTERVAL_MS = 60 * 1000;
 
 window.onload = function() {
+    // Served by `serve-report` as report.html?ex=<name>, the report is
+    // read from the API, and follows the progress of the run. Logs are
+    // then fetched from the API when opened.
+    let ex = new URLSearchParams(window.location.search).get("ex");
+    let configUrl = "config.json";
+    let resultsUrl = "results.json";
+    if (ex) {
+	api = `/api/ex/${encodeURIComponent(ex)}`;
+	configUrl = `${api}/config`;
+	resultsUrl = `${api}/results`;
+	pollProgress(`${api}/progress`);
+    }
+
     let configReq = new XMLHttpRequest();
     configReq.addEventListener("load", function() { loadConfig(configReq) });
     configReq.overrideMimeType("application/json");
-    configReq.open("GET", "config.json");
+    configReq.open("GET", configUrl);
     configReq.send();
 
     let resultsReq = new XMLHttpRequest();
     resultsReq.addEventListener("load", function() { loadResults(resultsReq) });
     resultsReq.overrideMimeType("application/json");
-    resultsReq.open("GET", "results.json");
+    resultsReq.open("GET", resultsUrl);
     resultsReq.send();
 
     setUpButtons();
+    setUpFilters();
 };
 
+function pollProgress(url) {
+    let req = new XMLHttpRequest();
+    req.addEventListener("load", function() {
+	let progress = JSON.parse(req.responseText);
+	insertProgress(progress);
+	let done = progress != null && progress.current.length == 0
+	    && Object.keys(progress.toolchains).every(function(tc) {
+		let count = progress.toolchains[tc];
+		return count.done == count.total;
+	    });
+	if (!done) {
+	    window.setTimeout(function() { pollProgress(url) }, PROGRESS_INTERVAL_MS);
+	}
+    });
+    req.overrideMimeType("application/json");
+    req.open("GET", url);
+    req.send();
+}
+
+function insertProgress(progress) {
+    let progressEl = document.getElementById("progress");
+    if (progress == null) {
+	progressEl.innerHTML = `<h2>Progress</h2><div>Not started yet</div>`;
+	return;
+    }
+
+    let html = `<h2>Progress</h2>`;
+    for (tc of Object.keys(progress.toolchains)) {
+	let count = progress.toolchains[tc];
+	let percent = count.total > 0 ? Math.floor(100 * count.done / count.total) : 100;
+	html += `<div>
+	    <span class="toolchain">${escapeHtml(tc)}</span>
+	    <progress max="${count.total}" value="${count.done}"></progress>
+	    <span>${count.done} / ${count.total} (${percent}%)</span>
+	</div>`;
+    }
+
+    let results = Object.keys(progress.results).map(function(r) {
+	return `${escapeHtml(r)}: ${progress.results[r]}`;
+    });
+    let eta = progress.eta_secs != null ? formatDuration(progress.eta_secs) : "unknown";
+    html += `<div>Results: ${results.join(", ") || "none yet"}</div>
+	<div>Remaining: ~${eta}</div>
+	<div>Updated: ${escapeHtml(progress.updated)}</div>`;
+
+    if (progress.current.length > 0) {
+	html += `<ul>`;
+	for (test of progress.current) {
+	    let agent = test.agent ? ` on ${escapeHtml(test.agent)}` : "";
+	    html += `<li>${escapeHtml(test.crate)} against ${escapeHtml(test.toolchain)}${agent}
+		since ${escapeHtml(test.started)}</li>`;
+	}
+	html += `</ul>`;
+    }
+
+    progressEl.innerHTML = html;
+}
+
+function formatDuration(secs) {
+    if (secs < 60 * 8) {
+	return `${secs} seconds`;
+    } else if (secs < 60 * 60 * 8) {
+	return `${Math.floor(secs / 60)} minutes`;
+    } else {
+	return `${Math.floor(secs / 60 / 60)} hours`;
+    }
+}
+
 function loadConfig(req) {
     config = JSON.parse(req.responseText);
     if (config != null && results != null) {
@@ -34,7 +121,7 @@ function loadResults(req) {
 function begin(config, results) {
     let nameEl = document.getElementById("ex-name");
 
-    nameEl.innerHTML = config.name;
+    nameEl.textContent = config.name;
 
     let tc1 = parseToolchain(config.toolchains[0]);
     let tc2 = parseToolchain(config.toolchains[1]);
@@ -42,8 +129,16 @@ function begin(config, results) {
     let tc1el = document.getElementById("ex-tc1");
     let tc2el = document.getElementById("ex-tc2");
 
-    tc1el.innerHTML = tc1;
-    tc2el.innerHTML = tc2;
+    tc1el.textContent = tc1;
+    tc2el.textContent = tc2;
+
+    let versions = results.toolchain_versions || [];
+    if (versions.length == 2) {
+	document.getElementById("ex-tc1-version").textContent =
+	    `${versions[0].rustc}, ${versions[0].cargo}`;
+	document.getElementById("ex-tc2-version").textContent =
+	    `${versions[1].rustc}, ${versions[1].cargo}`;
+    }
 
     let cratesEl = document.getElementById("ex-crates");
     cratesEl.innerHTML = config.crates.length;
@@ -64,10 +159,18 @@ function begin(config, results) {
     sameTestFailEl.innerHTML = summary.sameTestFail;
     sameTestPassEl.innerHTML = summary.sameTestPass;
 
+    insertSlowdowns(results.slowdowns || []);
+    insertBenchRegressions(results.bench_regressions || []);
+    insertClusters(results.clusters || []);
+    insertIces(results.ices || []);
+
+    let targets = config.targets || [];
+    insertTargetHeaders(targets);
+
     // Creating the document will take a second. Lay out the summary first.
     let results_ = results;
     window.setTimeout(function() {
-        insertResults(results_);
+        insertResults(results_, targets, [tc1, tc2]);
     }, 1);
 
     config = null;
@@ -118,7 +221,131 @@ function calcSummary(results) {
     };
 }
 
-function insertResults(results) {
+function insertSlowdowns(slowdowns) {
+    if (slowdowns.length == 0) {
+	return;
+    }
+
+    let slowdownsEl = document.getElementById("slowdowns");
+    let html = `<h2>Compile-time slowdowns</h2>
+	<div><span>name</span><span>metric</span><span>change</span><span>noise</span></div>`;
+
+    for (slowdown of slowdowns) {
+	let change = (slowdown.change * 100).toFixed(1);
+	let noise = (slowdown.noise * 100).toFixed(1);
+	html += `<div>
+	    <span>${escapeHtml(slowdown.name)}</span>
+	    <span>${escapeHtml(slowdown.metric)}</span>
+	    <span>+${change}%</span>
+	    <span>&plusmn;${noise}%</span>
+	</div>`;
+    }
+
+    slowdownsEl.innerHTML = html;
+}
+
+function insertBenchRegressions(regressions) {
+    if (regressions.length == 0) {
+	return;
+    }
+
+    let benchesEl = document.getElementById("bench-regressions");
+    let html = `<h2>Benchmark regressions</h2>
+	<div><span>name</span><span>benchmark</span><span>before</span><span>after</span><span>change</span></div>`;
+
+    for (regression of regressions) {
+	let change = (regression.change * 100).toFixed(1);
+	html += `<div>
+	    <span>${escapeHtml(regression.name)}</span>
+	    <span>${escapeHtml(regression.bench)}</span>
+	    <span>${regression.before} ns</span>
+	    <span>${regression.after} ns</span>
+	    <span>+${change}%</span>
+	</div>`;
+    }
+
+    benchesEl.innerHTML = html;
+}
+
+function insertClusters(clusters) {
+    if (clusters.length == 0) {
+	return;
+    }
+
+    let clustersEl = document.getElementById("clusters");
+    let html = `<h2>Regressions by root cause</h2>`;
+
+    for (cluster of clusters) {
+	let crates = cluster.crates.map(escapeHtml).join(", ");
+	html += `<details>
+	    <summary>
+	      <span class="count">${cluster.crates.length}</span>
+	      <span class="kind">${escapeHtml(cluster.kind)}</span>
+	      <span class="signature">${escapeHtml(cluster.signature)}</span>
+	    </summary>
+	    <div>${crates}</div>
+	</details>`;
+    }
+
+    clustersEl.innerHTML = html;
+}
+
+function insertIces(ices) {
+    if (ices.length == 0) {
+	return;
+    }
+
+    let icesEl = document.getElementById("ices");
+    let html = `<h2>Internal compiler errors</h2>`;
+
+    for (ice of ices) {
+	let location = ice.ice.location ? ` at ${escapeHtml(ice.ice.location)}` : "";
+	let backtrace = (ice.ice.backtrace || []).map(escapeHtml).join("\n");
+	let log = logUrl(ice.name, ice.toolchain, ice.log);
+	html += `<details>
+	    <summary>
+	      <span class="name"><a href="${escapeHtml(log)}">${escapeHtml(ice.name)}</a></span>
+	      <span class="toolchain">${escapeHtml(ice.toolchain)}</span>
+	      <span class="message">${escapeHtml(ice.ice.message)}${location}</span>
+	    </summary>
+	    <pre>${backtrace}</pre>
+	</details>`;
+    }
+
+    icesEl.innerHTML = html;
+}
+
+// Where the log of a crate's run is, `log` being its directory in a
+// generated report
+function logUrl(name, tc, log) {
+    if (api) {
+	return `${api}/crate/${encodeURIComponent(name)}/${encodeURIComponent(tc)}/log`;
+    }
+    return `${log}/log.txt`;
+}
+
+// Escapes text for use in HTML, both as content and as attribute values
+function escapeHtml(s) {
+    return String(s)
+	.replace(/&/g, "&amp;")
+	.replace(/</g, "&lt;")
+	.replace(/>/g, "&gt;")
+	.replace(/"/g, "&quot;")
+	.replace(/'/g, "&#39;");
+}
+
+function insertTargetHeaders(targets) {
+    let headerEl = document.querySelector("#results > div:first-child");
+
+    for (target of targets) {
+	let span = document.createElement("span");
+	span.classList.add("target");
+	span.textContent = target;
+	headerEl.appendChild(span);
+    }
+}
+
+function insertResults(results, targets, toolchains) {
     let resultsTableEl = document.getElementById("results");
 
     for (crate of results.crates) {
@@ -127,22 +354,53 @@ function insertResults(results) {
 	let run1 = parseRunResult(crate.runs[0]);
 	let run2 = parseRunResult(crate.runs[1]);
 
-        function runToHtml(run) {
+        function runToHtml(run, tc) {
             if (run.log) {
-	        return `<span><a href="${run.log}/log.txt">${run.res}</a></span>`;
+	        let log = escapeHtml(logUrl(name, tc, run.log));
+	        return `<span><a href="${log}">${run.res}</a></span>`;
             } else {
 	        return `<span>${run.res}</span>`;
             }
         }
 
-	let html1 = runToHtml(run1);
-	let html2 = runToHtml(run2);
+	let html1 = runToHtml(run1, toolchains[0]);
+	let html2 = runToHtml(run2, toolchains[1]);
+
+	let files = "";
+	if (crate.lockfile) {
+	    files += `<a href="${escapeHtml(crate.lockfile)}">lock</a> `;
+	}
+	if (crate.froml) {
+	    files += `<a href="${escapeHtml(crate.froml)}">toml</a>`;
+	}
+	let source = crate.source == "GitHub" ? "github" : "registry";
+
+	let targetHtml = "";
+	for (target of targets) {
+	    let comp = crate.targets ? crate.targets[target] : null;
+	    let class_ = jsonCrateResToCss(comp || "Unknown");
+	    targetHtml += `<span class="target ${class_}">${class_}</span>`;
+	}
+
+	let usageGrowth = escapeHtml((crate.usage_growth || []).join(", "));
+	let failingTests = escapeHtml((crate.failing_tests || []).join(", "));
+	let error = escapeHtml(crate.error || "");
+	if (crate.regressed_via) {
+	    error = `regressed via dep ${escapeHtml(crate.regressed_via)}`;
+	} else if (crate.downstream && crate.downstream.length > 0) {
+	    error += ` (breaks ${crate.downstream.length} downstream)`;
+	}
 
 	let row = `
-	<div class="${res}">
-	    <span>${name}</span>
+	<div class="${res} source-${source}" data-name="${escapeHtml(name)}">
+	    <span><a href="${escapeHtml(crate.url)}">${escapeHtml(name)}</a></span>
 	    ${html1}
 	    ${html2}
+	    <span class="files">${files}</span>
+	    <span class="usage-growth">${usageGrowth}</span>
+	    ${targetHtml}
+	    <span class="failing-tests" title="${failingTests}">${failingTests}</span>
+	    <span class="error">${error}</span>
         </div>
 	`;
 
@@ -193,11 +451,34 @@ function jsonRunResToDisplay(res) {
 	return "test-fail";
     } else if (res == "TestPass") {
 	return "test-pass";
+    } else if (res == "Ice") {
+	return "ice";
     } else {
 	throw "unknown test status";
     }
 }
 
+function setUpFilters() {
+    let searchEl = document.getElementById("search");
+    let registryEl = document.getElementById("show-registry");
+    let githubEl = document.getElementById("show-github");
+
+    function applyFilters() {
+	let query = searchEl.value.toLowerCase();
+	let rows = document.querySelectorAll("#results > div[data-name]");
+	for (row of rows) {
+	    let hidden = !row.dataset.name.toLowerCase().includes(query)
+		|| (!registryEl.checked && row.classList.contains("source-registry"))
+		|| (!githubEl.checked && row.classList.contains("source-github"));
+	    row.classList.toggle("filtered", hidden);
+	}
+    }
+
+    searchEl.addEventListener("input", applyFilters);
+    registryEl.addEventListener("change", applyFilters);
+    githubEl.addEventListener("change", applyFilters);
+}
+
 function setUpButtons() {
     let buttons = document.querySelectorAll("#controls > span");
 